@@ -0,0 +1,62 @@
+//! Compares walking parsed programs with running them compiled to bytecode,
+//! on large generated programs. Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use hw04::compiler::compile;
+use hw04::parser::{parse, run_compiled, run_parsed};
+use hw04::rpn::Stack;
+use hw04::words::Dictionary;
+
+/// A long line of arithmetic, half of it on constants that fold away.
+fn arithmetic(n: usize) -> String {
+    let mut src = String::from("1 !x 0 ");
+    for i in 0..n {
+        src.push_str(&format!("@x {} + {} 2 * - + ", i % 7, i % 5));
+    }
+    src
+}
+
+/// A loop that shuffles the stack on each turn.
+fn shuffling(n: usize) -> String {
+    format!("0 {} 0 do i <-> <-> + dup drop 1 2 <-> - + loop", n)
+}
+
+/// A word called from a loop, which stays a word after compiling.
+fn calls(n: usize) -> String {
+    format!(": sq dup * ; 0 {} 0 do i sq + 1000 % loop", n)
+}
+
+fn bench(c: &mut Criterion) {
+    let programs = [
+        ("arithmetic", arithmetic(10_000)),
+        ("shuffling", shuffling(10_000)),
+        ("calls", calls(10_000)),
+    ];
+    for (name, src) in programs {
+        let mut group = c.benchmark_group(name);
+        let words = Dictionary::new();
+        let program = parse(&src, &words).unwrap();
+        let code = compile(&program, &words);
+
+        group.bench_function("interpreted", |b| {
+            b.iter_batched(
+                || (Stack::with_seed(0), Dictionary::new()),
+                |(mut stack, mut words)| run_parsed(&mut stack, &mut words, &program).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function("compiled", |b| {
+            b.iter_batched(
+                || (Stack::with_seed(0), Dictionary::new()),
+                |(mut stack, mut words)| run_compiled(&mut stack, &mut words, &code).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function("compile", |b| b.iter(|| compile(&program, &words)));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,101 @@
+//! The syntax of programs: the tree of nodes the parser builds, and the
+//! literals in it.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ratio::Rational;
+use crate::rpn::Elt;
+use crate::units::{Quantity, Unit};
+
+/// A parsed program: a tree of nodes, with the bodies of control structures
+/// nested inside them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    /// A literal value to push.
+    Push(Elt),
+    /// A word to run: a user-defined word, an operator or `words`. Words are
+    /// looked up again when they run, so a redefinition takes effect.
+    Word(String),
+    /// `i` (0) or `j` (1): pushes the index of the innermost or next-outer
+    /// running `do` loop.
+    LoopIndex(usize),
+    /// `if then else then`: pops a boolean and runs one of the branches.
+    If(Vec<Node>, Vec<Node>),
+    /// `begin body until`: runs the body, then pops a boolean, until it's true.
+    Until(Vec<Node>),
+    /// `do body loop`: pops a start index and a limit, and runs the body for
+    /// each index from the start up to (but not including) the limit.
+    Loop(Vec<Node>),
+    /// `: name body ;`: defines a word.
+    Define(String, Vec<Node>),
+    /// `!name`: pops a value and stores it in the variable `name`.
+    Store(String),
+    /// `@name`: pushes the value of the variable `name`.
+    Fetch(String),
+    /// `to unit`: pops a quantity and shows it in `unit`, or pops a plain
+    /// number and makes it a quantity of `unit`.
+    Convert(Unit),
+}
+
+impl fmt::Display for Node {
+    /// Writes the node as it would be written in a program.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Node::Push(val) => write!(f, "{}", val),
+            Node::Word(name) => write!(f, "{}", name),
+            Node::LoopIndex(0) => write!(f, "i"),
+            Node::LoopIndex(_) => write!(f, "j"),
+            Node::If(then, els) if els.is_empty() => write!(f, "if {}then", Block(then)),
+            Node::If(then, els) => write!(f, "if {}else {}then", Block(then), Block(els)),
+            Node::Until(body) => write!(f, "begin {}until", Block(body)),
+            Node::Loop(body) => write!(f, "do {}loop", Block(body)),
+            Node::Define(name, body) => write!(f, ": {} {};", name, Block(body)),
+            Node::Store(name) => write!(f, "!{}", name),
+            Node::Fetch(name) => write!(f, "@{}", name),
+            Node::Convert(unit) => write!(f, "to {}", unit),
+        }
+    }
+}
+
+/// Writes a sequence of nodes, each followed by a space.
+pub struct Block<'a>(pub &'a [Node]);
+
+impl fmt::Display for Block<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for node in self.0 {
+            write!(f, "{} ", node)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a numeric literal: an integer (`12`), which may be too big for an
+/// `i32` with the `bigint` feature enabled, a fraction (`1/3`), a float
+/// (`3.5`, `1e-3`) or a quantity (`5m`, `9.8m/s^2`).
+pub fn parse_number(digits: &str) -> Option<Elt> {
+    if let Ok(val) = i32::from_str(digits) {
+        return Some(Elt::Int(val));
+    }
+    #[cfg(feature = "bigint")]
+    if let Ok(val) = num_bigint::BigInt::from_str(digits) {
+        return Some(Elt::from_big(val));
+    }
+    if let Some((num, den)) = digits.split_once('/') {
+        if let (Ok(num), Ok(den)) = (i64::from_str(num), i64::from_str(den)) {
+            return Rational::new(num, den).map(Elt::from_rational);
+        }
+    }
+    // A float needs a point or an exponent, so that an integer that's too big
+    // isn't silently rounded. The only other floats are the ones that don't
+    // print with digits, so that every value reads back as it prints.
+    if matches!(digits, "inf" | "-inf" | "NaN") {
+        return f64::from_str(digits).ok().map(Elt::Float);
+    }
+    if digits.contains(|c: char| c.is_ascii_digit()) && digits.contains(['.', 'e', 'E']) {
+        if let Ok(val) = f64::from_str(digits) {
+            return Some(Elt::Float(val));
+        }
+    }
+    Quantity::parse(digits).map(Elt::Quantity)
+}
@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+
+use crate::ast::Node;
+use crate::rpn::{Elt, Op, Stack};
+use crate::words::Dictionary;
+
+/// An instruction of a compiled program.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instr {
+    /// Pushes a constant.
+    Push(Elt),
+    /// Runs an operator, without looking up its name.
+    Op(Op),
+    /// Fails with `Error::Underflow(name)` unless the stack holds at least
+    /// `n` values. This is what's left of operators that the optimizer
+    /// removed, so that the program still fails the same way.
+    Need(usize, &'static str),
+    /// Pops a boolean and runs one of the branches.
+    If(Vec<Instr>, Vec<Instr>),
+    /// Runs the body, then pops a boolean, until it's true.
+    Until(Vec<Instr>),
+    /// Pops a start index and a limit, and runs the body for each index.
+    Loop(Vec<Instr>),
+    /// A node that the machine runs as it would a parsed program: a
+    /// user-defined or native word, one of the machine's words, a definition,
+    /// a variable or a loop index.
+    Node(Node),
+}
+
+/// Compiles a parsed program to bytecode, and optimizes it.
+///
+/// Operators are resolved once, here, rather than each time they run, so a
+/// name is only compiled to an operator if `words` doesn't define it and the
+/// program doesn't define it either. Constant expressions are folded, so
+/// `2 3 +` compiles to `5`, and the peephole rules remove pairs of operators
+/// that undo each other, like `<-> <->`.
+pub fn compile(program: &[Node], words: &Dictionary) -> Vec<Instr> {
+    let mut defined = HashSet::new();
+    collect_defined(program, &mut defined);
+    let mut compiler = Compiler { words, defined, scratch: Stack::with_seed(0) };
+    compiler.block(program)
+}
+
+/// Adds the names of the words that `program` defines to `defined`, looking
+/// inside the bodies of control structures as well as at the top level.
+fn collect_defined<'a>(program: &'a [Node], defined: &mut HashSet<&'a str>) {
+    for node in program {
+        match node {
+            Node::Define(name, body) => {
+                defined.insert(name.as_str());
+                collect_defined(body, defined);
+            }
+            Node::If(then, els) => {
+                collect_defined(then, defined);
+                collect_defined(els, defined);
+            }
+            Node::Until(body) | Node::Loop(body) => collect_defined(body, defined),
+            _ => {}
+        }
+    }
+}
+
+struct Compiler<'a> {
+    words: &'a Dictionary,
+    /// The words the program defines.
+    defined: HashSet<&'a str>,
+    /// A stack to evaluate constant expressions on.
+    scratch: Stack,
+}
+
+impl Compiler<'_> {
+    fn block(&mut self, program: &[Node]) -> Vec<Instr> {
+        let mut code = vec![];
+        for node in program {
+            let instr = match node {
+                Node::Push(val) => Instr::Push(val.clone()),
+                Node::Word(name) => match self.operator(name) {
+                    Some(op) => Instr::Op(op),
+                    None => Instr::Node(node.clone()),
+                },
+                Node::If(then, els) => Instr::If(self.block(then), self.block(els)),
+                Node::Until(body) => Instr::Until(self.block(body)),
+                Node::Loop(body) => Instr::Loop(self.block(body)),
+                node => Instr::Node(node.clone()),
+            };
+            self.emit(&mut code, instr);
+        }
+        code
+    }
+
+    /// The operator that `name` stands for, unless a word replaces it.
+    fn operator(&self, name: &str) -> Option<Op> {
+        let word = self.words.get(name).is_some() || self.words.native(name).is_some();
+        if word || self.defined.contains(name) {
+            return None;
+        }
+        Op::from_name(name)
+    }
+
+    /// Appends an instruction, then optimizes the end of the code.
+    fn emit(&mut self, code: &mut Vec<Instr>, instr: Instr) {
+        if let Instr::Op(op) = instr {
+            if self.fold(code, op) {
+                return;
+            }
+        }
+        code.push(instr);
+
+        let n = code.len();
+        let rule = match &code[n.saturating_sub(2)..] {
+            [Instr::Op(Op::Swap), Instr::Op(Op::Swap)] => Some(Instr::Need(2, Op::Swap.name())),
+            [Instr::Op(Op::Dup), Instr::Op(Op::Drop)] => Some(Instr::Need(1, Op::Dup.name())),
+            [Instr::Op(Op::Over), Instr::Op(Op::Drop)] => Some(Instr::Need(2, Op::Over.name())),
+            _ => None,
+        };
+        if let Some(need) = rule {
+            code.truncate(n - 2);
+            self.emit(code, need);
+        } else if let Some(&Instr::Need(need, _)) = code.last() {
+            // The check can't fail if the values are pushed right before it.
+            let pushed = code[..n - 1].iter().rev().take_while(|instr| matches!(instr, Instr::Push(_))).count();
+            if pushed >= need {
+                code.pop();
+            }
+        }
+    }
+
+    /// Folds `op` into the constants at the end of the code, if it takes only
+    /// those and can't fail on them. Returns whether it did.
+    fn fold(&mut self, code: &mut Vec<Instr>, op: Op) -> bool {
+        let Some(arity) = pure_arity(op) else { return false };
+        if code.len() < arity || !code[code.len() - arity..].iter().all(|instr| matches!(instr, Instr::Push(_))) {
+            return false;
+        }
+        self.scratch.eval(Op::Clear).expect("Clear failed");
+        for instr in &code[code.len() - arity..] {
+            if let Instr::Push(val) = instr {
+                self.scratch.push(val.clone()).expect("Push failed");
+            }
+        }
+        // An operator that fails is left to fail when the program runs.
+        if self.scratch.eval(op).is_err() {
+            return false;
+        }
+        code.truncate(code.len() - arity);
+        code.extend(self.scratch.as_slice().iter().cloned().map(Instr::Push));
+        true
+    }
+}
+
+/// How many values `op` takes, if it only depends on them: it doesn't look at
+/// the rest of the stack, the random number generator or the outside world.
+fn pure_arity(op: Op) -> Option<usize> {
+    match op {
+        Op::Neg | Op::Dup | Op::Drop | Op::Len => Some(1),
+        Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod | Op::Pow
+        | Op::Lt | Op::Gt | Op::Le | Op::Ge | Op::Eq
+        | Op::Swap | Op::Over | Op::Nip | Op::Tuck | Op::Concat => Some(2),
+        Op::Rot => Some(3),
+        Op::Rand | Op::Seed | Op::Pick | Op::Roll | Op::Depth | Op::Clear | Op::Quit => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn compile_src(src: &str, words: &Dictionary) -> Vec<Instr> {
+        compile(&parse(src, words).unwrap(), words)
+    }
+
+    #[test]
+    fn test_fold() {
+        let words = Dictionary::new();
+        assert_eq!(compile_src("2 3 +", &words), vec![Instr::Push(Elt::Int(5))]);
+        assert_eq!(compile_src("1 2 <-> - 4 * 3 drop", &words), vec![Instr::Push(Elt::Int(4))]);
+        assert_eq!(
+            compile_src("\"ab\" dup concat len 1 2 3 rot", &words),
+            [4, 2, 3, 1].map(|x| Instr::Push(Elt::Int(x))).to_vec()
+        );
+        // Operators that would fail, or that depend on more than their
+        // operands, are left alone.
+        assert_eq!(compile_src("1 0 /", &words)[2], Instr::Op(Op::Div));
+        assert_eq!(compile_src("10 #", &words)[1], Instr::Op(Op::Rand));
+        assert_eq!(compile_src("1 +", &words)[1], Instr::Op(Op::Add));
+    }
+
+    #[test]
+    fn test_peephole() {
+        let words = Dictionary::new();
+        assert_eq!(compile_src("<-> <->", &words), vec![Instr::Need(2, "<->")]);
+        assert_eq!(compile_src("dup drop ~", &words), vec![Instr::Need(1, "dup"), Instr::Op(Op::Neg)]);
+        assert_eq!(compile_src("1 over drop", &words), vec![Instr::Push(Elt::Int(1)), Instr::Need(2, "over")]);
+        assert_eq!(compile_src("1 2 over drop", &words), compile_src("1 2", &words));
+        assert_eq!(
+            compile_src("begin <-> <-> true until", &words),
+            vec![Instr::Until(vec![Instr::Need(2, "<->"), Instr::Push(Elt::Bool(true))])]
+        );
+    }
+
+    #[test]
+    fn test_words_replace_operators() {
+        let mut words = Dictionary::new();
+        words.define("dup", vec![]);
+        assert!(matches!(compile_src("1 dup", &words)[1], Instr::Node(Node::Word(_))));
+        let code = compile_src(": + * ; 2 3 +", &Dictionary::new());
+        assert!(matches!(code[3], Instr::Node(Node::Word(_))));
+
+        // The parser only allows definitions at the top, but a program built
+        // by hand may define words anywhere.
+        let define = Node::Define("+".to_string(), vec![Node::Word("*".to_string())]);
+        let program = [Node::If(vec![define], vec![]), Node::Word("+".to_string())];
+        assert!(matches!(compile(&program, &Dictionary::new())[1], Instr::Node(Node::Word(_))));
+    }
+}
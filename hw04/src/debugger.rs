@@ -0,0 +1,216 @@
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+use crate::ast::Node;
+use crate::rpn::{self, Op, Stack};
+
+/// Watches a program as it runs, for tracing and debugging. Programs that are
+/// watched are walked as parsed, rather than compiled, so that every node
+/// can be seen.
+pub trait Observer {
+    /// Called before each node runs, `depth` user-defined words deep. An error
+    /// stops the program with that error.
+    fn before(&mut self, _node: &Node, _stack: &Stack, _depth: usize) -> rpn::Result<()> {
+        Ok(())
+    }
+
+    /// Called after each operator runs.
+    fn after_op(&mut self, _op: Op, _stack: &Stack) {}
+}
+
+/// Two observers watching the same program, like a tracer and a debugger
+/// when `--trace` is combined with breakpoints. The first is told first, and
+/// if it stops the program the second isn't told.
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn before(&mut self, node: &Node, stack: &Stack, depth: usize) -> rpn::Result<()> {
+        self.0.before(node, stack, depth)?;
+        self.1.before(node, stack, depth)
+    }
+
+    fn after_op(&mut self, op: Op, stack: &Stack) {
+        self.0.after_op(op, stack);
+        self.1.after_op(op, stack);
+    }
+}
+
+/// Writes the operator and the stack after each operator runs, for `--trace`.
+pub struct Tracer<W: Write> {
+    out: W,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W) -> Tracer<W> {
+        Tracer { out }
+    }
+}
+
+impl<W: Write> Observer for Tracer<W> {
+    fn after_op(&mut self, op: Op, stack: &Stack) {
+        // Tracing is best-effort, and mustn't stop the program.
+        let _ = writeln!(self.out, "{:>8} | {}", op.name(), show(stack));
+    }
+}
+
+/// When the debugger next pauses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resume {
+    /// Before the next node, even inside a word.
+    Step,
+    /// Before the next node at most this many words deep, so words run
+    /// without pausing inside them.
+    Next(usize),
+    /// At the next call of a word with a breakpoint.
+    Continue,
+}
+
+/// An interactive debugger, which pauses a program to show the next node
+/// and the stack, and reads commands from `input` to decide how to go on:
+///
+/// * `step` (or `s`, or an empty line) runs the next node, stepping into
+///   words.
+/// * `next` (or `n`) runs the next node, stepping over words.
+/// * `continue` (or `c`) runs to the next breakpoint.
+/// * `stack` prints the whole stack with the type of each value.
+/// * `break WORD` and `unbreak WORD` set and remove breakpoints.
+/// * `stop` stops the program with `Error::Stopped`.
+pub struct Debugger<'b, R: BufRead, W: Write> {
+    input: R,
+    out: W,
+    /// The words to pause at when they're called.
+    breakpoints: &'b mut BTreeSet<String>,
+    resume: Resume,
+}
+
+impl<'b, R: BufRead, W: Write> Debugger<'b, R, W> {
+    pub fn new(input: R, out: W, breakpoints: &'b mut BTreeSet<String>, resume: Resume) -> Self {
+        Debugger { input, out, breakpoints, resume }
+    }
+
+    fn pauses_at(&self, node: &Node, depth: usize) -> bool {
+        match self.resume {
+            Resume::Step => true,
+            Resume::Next(max) => depth <= max,
+            Resume::Continue => matches!(node, Node::Word(name) if self.breakpoints.contains(name)),
+        }
+    }
+
+    /// Shows where the program is, and reads commands until one resumes it.
+    fn pause(&mut self, node: &Node, stack: &Stack, depth: usize) -> std::io::Result<Option<Resume>> {
+        writeln!(self.out, "{}-> {}", "  ".repeat(depth), node)?;
+        writeln!(self.out, "   stack: {}", show(stack))?;
+        loop {
+            write!(self.out, "(debug) ")?;
+            self.out.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                // End of input: stop rather than wait forever.
+                return Ok(None);
+            }
+            let line = line.trim();
+            let (command, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match (command, arg.trim()) {
+                ("" | "s" | "step", "") => return Ok(Some(Resume::Step)),
+                ("n" | "next", "") => return Ok(Some(Resume::Next(depth))),
+                ("c" | "continue", "") => return Ok(Some(Resume::Continue)),
+                ("stop", "") => return Ok(None),
+                ("stack", "") => {
+                    for (i, val) in stack.as_slice().iter().rev().enumerate() {
+                        writeln!(self.out, "{:4}: {:<8} {}", i, val.type_name(), val)?;
+                    }
+                }
+                ("break", word) if !word.is_empty() => {
+                    self.breakpoints.insert(word.to_string());
+                }
+                ("unbreak", word) if !word.is_empty() => {
+                    self.breakpoints.remove(word);
+                }
+                _ => writeln!(self.out, "commands: step, next, continue, stack, break WORD, unbreak WORD, stop")?,
+            }
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Observer for Debugger<'_, R, W> {
+    fn before(&mut self, node: &Node, stack: &Stack, depth: usize) -> rpn::Result<()> {
+        if !self.pauses_at(node, depth) {
+            return Ok(());
+        }
+        match self.pause(node, stack, depth).map_err(rpn::Error::IO)? {
+            Some(resume) => {
+                self.resume = resume;
+                Ok(())
+            }
+            None => Err(rpn::Error::Stopped),
+        }
+    }
+}
+
+/// The values on the stack, from the bottom up.
+fn show(stack: &Stack) -> String {
+    let vals: Vec<String> = stack.as_slice().iter().map(|val| val.to_string()).collect();
+    vals.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::evaluate_observed;
+    use crate::words::Dictionary;
+
+    /// Runs `src` under the debugger, with `commands` as its input, and
+    /// returns the result and what it wrote.
+    fn debug(src: &str, commands: &str, breakpoints: &[&str], resume: Resume) -> (rpn::Result<()>, String) {
+        let mut breakpoints = breakpoints.iter().map(|word| word.to_string()).collect();
+        let mut out = vec![];
+        let mut debugger = Debugger::new(commands.as_bytes(), &mut out, &mut breakpoints, resume);
+        let mut stack = Stack::new();
+        let res = evaluate_observed(&mut stack, &mut Dictionary::new(), src, &mut debugger);
+        (res, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut out = vec![];
+        let mut stack = Stack::new();
+        evaluate_observed(&mut stack, &mut Dictionary::new(), "1 2 + dup *", &mut Tracer::new(&mut out)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "       + | 3\n     dup | 3 3\n       * | 9\n");
+    }
+
+    #[test]
+    fn test_step() {
+        let (res, out) = debug(": sq dup * ; 3 sq", "s\nn\ns\nc\n", &[], Resume::Step);
+        assert!(res.is_ok());
+        // Without a terminal to echo the commands, output follows the prompts.
+        let pauses: Vec<&str> = out
+            .lines()
+            .map(|line| line.trim_start_matches("(debug) "))
+            .filter(|line| line.contains("-> "))
+            .collect();
+        assert_eq!(pauses, vec!["-> : sq dup * ;", "-> 3", "-> sq", "  -> dup"]);
+        assert!(out.contains("   stack: 3\n"));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let src = ": sq dup * ; : quad sq sq ; 2 quad 1 +";
+        let (res, out) = debug(src, "stack\nc\nunbreak sq\nc\n", &["sq"], Resume::Continue);
+        assert!(res.is_ok());
+        assert_eq!(out.matches("-> sq").count(), 2);
+        assert!(out.contains("   0: Int      2\n"));
+
+        let (res, _) = debug(src, "stop\n", &["quad"], Resume::Continue);
+        assert!(matches!(res, Err(rpn::Error::Stopped)));
+    }
+
+    #[test]
+    fn test_trace_with_breakpoints() {
+        let (mut trace, mut out) = (vec![], vec![]);
+        let mut breakpoints = ["sq".to_string()].into();
+        let debugger = Debugger::new("c\n".as_bytes(), &mut out, &mut breakpoints, Resume::Continue);
+        let mut both = (Tracer::new(&mut trace), debugger);
+        let res = evaluate_observed(&mut Stack::new(), &mut Dictionary::new(), ": sq dup * ; 3 sq", &mut both);
+        assert!(res.is_ok());
+        assert_eq!(String::from_utf8(trace).unwrap(), "     dup | 3 3\n       * | 9\n");
+        assert!(String::from_utf8(out).unwrap().contains("-> sq"));
+    }
+}
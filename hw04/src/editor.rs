@@ -0,0 +1,168 @@
+use std::env;
+use std::io;
+use std::path::PathBuf;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use crate::parser::{builtin_words, REPL_COMMANDS};
+use crate::rpn;
+use crate::words::Dictionary;
+
+/// The file in the home directory that keeps the lines typed into the REPL
+/// between runs.
+pub const HISTORY_FILE: &str = ".hw04_history";
+
+/// Reads the lines of a REPL session, which runs on a terminal. Lines can be
+/// edited with the arrow keys, earlier lines (from this run or a previous one)
+/// are recalled with up and down, and tab completes words and commands.
+pub struct LineReader {
+    editor: Box<Editor<WordCompleter, DefaultHistory>>,
+    /// Where the history is kept, if there's a home directory.
+    history: Option<PathBuf>,
+}
+
+impl LineReader {
+    /// Creates a line editor, which fails if the terminal can't be set up.
+    pub fn new() -> rpn::Result<LineReader> {
+        let mut editor = Editor::new().map_err(|err| rpn::Error::IO(io::Error::other(err)))?;
+        editor.set_helper(Some(WordCompleter::default()));
+        let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        if let Some(path) = &history {
+            // There's no history file before the first run.
+            let _ = editor.load_history(path);
+        }
+        Ok(LineReader { editor: Box::new(editor), history })
+    }
+
+    /// Reads a line after printing `prompt`, or returns `None` at the end of
+    /// input. `words` are the user's words and variables, for completion.
+    pub fn read_line(&mut self, prompt: &str, words: &Dictionary) -> rpn::Result<Option<String>> {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.sync(words);
+        }
+        match self.editor.readline(prompt) {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    let _ = self.editor.add_history_entry(line.as_str());
+                    // The history file is a convenience, so a session goes on
+                    // without it if it can't be written.
+                    if let Some(path) = &self.history {
+                        let _ = self.editor.append_history(path);
+                    }
+                }
+                Ok(Some(line))
+            }
+            // Ctrl-C abandons the line being typed.
+            Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
+            Err(ReadlineError::Eof) => Ok(None),
+            Err(ReadlineError::Io(err)) => Err(rpn::Error::IO(err)),
+            Err(err) => Err(rpn::Error::IO(io::Error::other(err))),
+        }
+    }
+}
+
+/// Completes the word under the cursor from the builtin words, the user's
+/// words and, after `!` or `@`, the user's variables. The first word of a line
+/// may also be a REPL command.
+#[derive(Default)]
+pub struct WordCompleter {
+    words: Vec<String>,
+    vars: Vec<String>,
+}
+
+impl WordCompleter {
+    /// Catches up with the words and variables the user has defined.
+    pub fn sync(&mut self, words: &Dictionary) {
+        let names = words.names().into_iter().chain(words.native_names());
+        self.words = names.map(String::from).collect();
+        self.vars = words.vars().into_iter().map(|(name, _)| name.to_string()).collect();
+    }
+
+    /// The words that `prefix` could be the start of, in alphabetical order.
+    /// Commands are only candidates for the `first` word of a line.
+    pub fn candidates(&self, prefix: &str, first: bool) -> Vec<String> {
+        let commands = REPL_COMMANDS.iter().copied().filter(|_| first);
+        let mut candidates: Vec<String> = match prefix.chars().next() {
+            Some(sigil @ ('!' | '@')) => self
+                .vars
+                .iter()
+                .filter(|name| name.starts_with(&prefix[1..]))
+                .map(|name| format!("{}{}", sigil, name))
+                .collect(),
+            _ => self
+                .words
+                .iter()
+                .map(String::as_str)
+                .chain(builtin_words())
+                .chain(commands)
+                .filter(|word| word.starts_with(prefix))
+                .map(String::from)
+                .collect(),
+        };
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+}
+
+impl Completer for WordCompleter {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        // Words end at whitespace and brackets, as in the tokenizer.
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| c.is_whitespace() || matches!(c, '[' | ']' | '{' | '}'))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let first = line[..start].trim().is_empty();
+        Ok((start, self.candidates(&line[start..pos], first)))
+    }
+}
+
+impl Hinter for WordCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for WordCompleter {}
+
+impl Validator for WordCompleter {}
+
+impl Helper for WordCompleter {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpn::Elt;
+
+    #[test]
+    fn test_candidates() {
+        let mut words = Dictionary::new();
+        words.define("double", vec![]);
+        words.store("depth0", Elt::Int(0));
+        let mut completer = WordCompleter::default();
+        completer.sync(&words);
+
+        assert_eq!(completer.candidates("d", false), vec!["depth", "do", "double", "drop", "dup"]);
+        assert_eq!(completer.candidates("@de", false), vec!["@depth0"]);
+        assert_eq!(completer.candidates("!", false), vec!["!depth0"]);
+        assert!(completer.candidates("xyz", false).is_empty());
+
+        // Commands only start a line.
+        assert_eq!(completer.candidates("d", true), vec!["debug", "depth", "do", "double", "drop", "dup"]);
+        assert_eq!(completer.candidates(".", true), vec![".infix", ".stack"]);
+        assert_eq!(completer.candidates("un", true), vec!["unbreak", "undo", "until"]);
+        assert!(completer.candidates(".", false).is_empty());
+    }
+}
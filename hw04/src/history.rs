@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::rpn::Stack;
+use crate::words::Dictionary;
+
+/// How many lines `undo` can step back through.
+pub const MAX_HISTORY: usize = 100;
+
+/// The state of a session at some point.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub stack: Stack,
+    pub words: Dictionary,
+}
+
+impl fmt::Display for Snapshot {
+    /// Writes the state as a program that rebuilds it: the words and
+    /// variables, then the values on the stack from the bottom up.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.words)?;
+        let vals: Vec<String> = self.stack.as_slice().iter().map(|val| val.to_string()).collect();
+        if !vals.is_empty() {
+            writeln!(f, "{}", vals.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A line that was evaluated, and the state it was evaluated in.
+#[derive(Debug)]
+struct Entry {
+    line: String,
+    before: Snapshot,
+}
+
+/// A bounded history of the lines evaluated in a session, for `undo` and
+/// `redo`.
+#[derive(Debug, Default)]
+pub struct History {
+    done: VecDeque<Entry>,
+    /// Lines that were undone, most recently undone last. Each keeps the
+    /// state from after it ran, to return to on `redo`.
+    undone: Vec<Entry>,
+}
+
+impl History {
+    /// Creates an empty history.
+    pub fn new() -> History {
+        History { done: VecDeque::new(), undone: vec![] }
+    }
+
+    /// Records that `line` was evaluated in the state `before`. This forgets
+    /// any lines that were undone, and the oldest line if the history is full.
+    pub fn record(&mut self, line: &str, before: Snapshot) {
+        if self.done.len() == MAX_HISTORY {
+            self.done.pop_front();
+        }
+        self.done.push_back(Entry { line: line.trim().to_string(), before });
+        self.undone.clear();
+    }
+
+    /// Steps back over the last line, returning the state from before it, or
+    /// `None` if there's nothing to undo. `now` is the current state.
+    pub fn undo(&mut self, now: Snapshot) -> Option<Snapshot> {
+        let entry = self.done.pop_back()?;
+        self.undone.push(Entry { line: entry.line, before: now });
+        Some(entry.before)
+    }
+
+    /// Steps forward over the last undone line, returning the state from
+    /// after it, or `None` if there's nothing to redo. `now` is the current
+    /// state.
+    pub fn redo(&mut self, now: Snapshot) -> Option<Snapshot> {
+        let entry = self.undone.pop()?;
+        self.done.push_back(Entry { line: entry.line, before: now });
+        Some(entry.before)
+    }
+
+    /// The lines that `undo` can step back over, oldest first.
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.done.iter().map(|entry| entry.line.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpn::Elt;
+
+    fn snapshot(vals: &[i32]) -> Snapshot {
+        let mut stack = Stack::new();
+        for &x in vals {
+            stack.push(Elt::Int(x)).unwrap();
+        }
+        Snapshot { stack, words: Dictionary::new() }
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut history = History::new();
+        history.record("1", snapshot(&[]));
+        history.record("2", snapshot(&[1]));
+
+        let now = history.undo(snapshot(&[1, 2])).unwrap();
+        assert_eq!(now.stack.as_slice(), &[Elt::Int(1)]);
+        let now = history.undo(now).unwrap();
+        assert!(now.stack.is_empty());
+        assert!(history.undo(snapshot(&[])).is_none());
+
+        let now = history.redo(now).unwrap();
+        assert_eq!(now.stack.as_slice(), &[Elt::Int(1)]);
+        assert_eq!(history.lines().collect::<Vec<_>>(), vec!["1"]);
+    }
+
+    #[test]
+    fn test_record_forgets_redo() {
+        let mut history = History::new();
+        history.record("1", snapshot(&[]));
+        history.undo(snapshot(&[1])).unwrap();
+        history.record("3", snapshot(&[]));
+        assert!(history.redo(snapshot(&[3])).is_none());
+    }
+
+    #[test]
+    fn test_bounded() {
+        let mut history = History::new();
+        for i in 0..MAX_HISTORY + 5 {
+            history.record(&i.to_string(), snapshot(&[]));
+        }
+        assert_eq!(history.lines().count(), MAX_HISTORY);
+        assert_eq!(history.lines().next(), Some("5"));
+    }
+}
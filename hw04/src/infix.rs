@@ -0,0 +1,413 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::ast::{parse_number, Block, Node};
+use crate::rpn::{self, Elt, Op, SyntaxError};
+
+/// Whether programs are read in the calculator's own postfix notation, or as
+/// infix expressions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Notation {
+    Postfix,
+    Infix,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Assoc {
+    Left,
+    Right,
+}
+
+/// The operators of infix expressions, with how tightly they bind and which
+/// way they group. Both the converter and the printer use this table, so
+/// that printing an expression reads back as the same program.
+const OPERATORS: [(Op, &str, u8, Assoc); 12] = [
+    (Op::Eq, "=", 1, Assoc::Left),
+    (Op::Lt, "<", 2, Assoc::Left),
+    (Op::Gt, ">", 2, Assoc::Left),
+    (Op::Le, "<=", 2, Assoc::Left),
+    (Op::Ge, ">=", 2, Assoc::Left),
+    (Op::Add, "+", 3, Assoc::Left),
+    (Op::Sub, "-", 3, Assoc::Left),
+    (Op::Mul, "*", 4, Assoc::Left),
+    (Op::Div, "/", 4, Assoc::Left),
+    (Op::Mod, "%", 4, Assoc::Left),
+    // Negation is written `-x`, and binds looser than `^` so that `-2 ^ 2`
+    // is `-(2 ^ 2)`.
+    (Op::Neg, "-", 5, Assoc::Right),
+    (Op::Pow, "^", 6, Assoc::Right),
+];
+
+/// How tightly an operand binds: tighter than any operator.
+const ATOM: u8 = 7;
+
+/// Looks up an operator in the table.
+fn operator(op: Op) -> Option<(&'static str, u8, Assoc)> {
+    OPERATORS.iter().find(|entry| entry.0 == op).map(|&(_, symbol, prec, assoc)| (symbol, prec, assoc))
+}
+
+/// Looks up the binary operator written `symbol`.
+fn binary(symbol: &str) -> Option<Op> {
+    OPERATORS.iter().find(|entry| entry.1 == symbol && entry.0 != Op::Neg).map(|entry| entry.0)
+}
+
+/// A token of an infix expression, and where it is in the input.
+#[derive(Clone, Copy, Debug)]
+struct Token<'a> {
+    text: &'a str,
+    start: usize,
+}
+
+impl Token<'_> {
+    fn span(&self) -> Range<usize> {
+        self.start..self.start + self.text.len()
+    }
+}
+
+/// Splits an infix expression into numbers, names, operators and
+/// parentheses. `offset` is where `src` starts in the whole input.
+fn tokenize(src: &str, offset: usize) -> rpn::Result<Vec<Token<'_>>> {
+    let mut tokens = vec![];
+    let mut chars = src.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut end = start + c.len_utf8();
+        if c.is_whitespace() {
+            continue;
+        } else if c.is_ascii_digit() || c == '.' {
+            // A number, which may have an exponent with a sign, like `1e-3`.
+            let mut prev = c;
+            while let Some(&(i, c)) = chars.peek() {
+                let sign = matches!(c, '+' | '-') && matches!(prev, 'e' | 'E');
+                if !(c.is_ascii_alphanumeric() || c == '.' || sign) {
+                    break;
+                }
+                prev = c;
+                end = i + c.len_utf8();
+                chars.next();
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+        } else if matches!(c, '<' | '>') && chars.peek().is_some_and(|&(_, c)| c == '=') {
+            end += 1;
+            chars.next();
+        } else if !"()+-*/%^<>=".contains(c) {
+            let token = Token { text: &src[start..end], start: start + offset };
+            return Err(error(token, format!("`{}` can't be in an infix expression", c), None));
+        }
+        tokens.push(Token { text: &src[start..end], start: start + offset });
+    }
+    Ok(tokens)
+}
+
+/// Something waiting on the operator stack of the shunting-yard algorithm.
+enum Pending<'a> {
+    Op(Op),
+    Paren(Token<'a>),
+}
+
+/// Converts an infix expression such as `(3 + 4) * 2` to the program that
+/// computes it, `3 4 + 2 *`, with the shunting-yard algorithm. Names stand
+/// for variables, so `x * x` is `@x @x *`. `offset` is where `src` starts in
+/// the whole input, for the spans of syntax errors.
+pub fn to_postfix(src: &str, offset: usize) -> rpn::Result<Vec<Node>> {
+    let mut out = vec![];
+    let mut pending: Vec<Pending> = vec![];
+    // Whether the next token should start an operand, rather than be a
+    // binary operator or a closing parenthesis.
+    let mut operand = true;
+
+    for token in tokenize(src, offset)? {
+        match token.text {
+            "(" if operand => pending.push(Pending::Paren(token)),
+            ")" if !operand => loop {
+                match pending.pop() {
+                    Some(Pending::Op(op)) => out.push(word(op)),
+                    Some(Pending::Paren(_)) => break,
+                    None => {
+                        let message = "`)` without a matching `(`".to_string();
+                        return Err(error(token, message, None));
+                    }
+                }
+            },
+            "-" if operand => pending.push(Pending::Op(Op::Neg)),
+            text if !operand && binary(text).is_some() => {
+                let op = binary(text).unwrap();
+                let (_, prec, assoc) = operator(op).unwrap();
+                // Pop the operators that bind at least as tightly as this one,
+                // and so take the operand to its left.
+                while let Some(&Pending::Op(top)) = pending.last() {
+                    let (_, top_prec, _) = operator(top).unwrap();
+                    if top_prec < prec || (top_prec == prec && assoc == Assoc::Right) {
+                        break;
+                    }
+                    out.push(word(top));
+                    pending.pop();
+                }
+                pending.push(Pending::Op(op));
+                operand = true;
+            }
+            text if operand => {
+                out.push(operand_node(token, text)?);
+                operand = false;
+            }
+            _ => {
+                let message = format!("expected an operator before `{}`", token.text);
+                return Err(error(token, message, None));
+            }
+        }
+    }
+
+    if operand {
+        let token = Token { text: "", start: offset + src.trim_end().len() };
+        return Err(error(token, "the expression is missing an operand".to_string(), None));
+    }
+    while let Some(pending) = pending.pop() {
+        match pending {
+            Pending::Op(op) => out.push(word(op)),
+            Pending::Paren(token) => {
+                let message = "`(` is never closed".to_string();
+                return Err(error(token, message, Some("add `)`".to_string())));
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Converts a script of infix expressions, one to a line, to the postfix
+/// program that computes them in turn.
+pub fn translate(src: &str) -> rpn::Result<String> {
+    let mut out = String::new();
+    let mut offset = 0;
+    for line in src.split_inclusive('\n') {
+        if !line.trim().is_empty() {
+            out.push_str(&format!("{}\n", Block(&to_postfix(line, offset)?)));
+        }
+        offset += line.len();
+    }
+    Ok(out)
+}
+
+fn word(op: Op) -> Node {
+    Node::Word(op.name().to_string())
+}
+
+fn operand_node(token: Token, text: &str) -> rpn::Result<Node> {
+    if text.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return match parse_number(text) {
+            // Fractions are written with `/`, so they aren't infix literals.
+            Some(val) => Ok(Node::Push(val)),
+            None => Err(error(token, format!("`{}` isn't a number", text), None)),
+        };
+    }
+    match text {
+        "true" => Ok(Node::Push(Elt::Bool(true))),
+        "false" => Ok(Node::Push(Elt::Bool(false))),
+        text if text.starts_with(|c: char| c.is_alphabetic() || c == '_') => {
+            Ok(Node::Fetch(text.to_string()))
+        }
+        text => Err(error(token, format!("expected a number or a name, not `{}`", text), None)),
+    }
+}
+
+fn error(token: Token, message: String, suggestion: Option<String>) -> rpn::Error {
+    rpn::Error::Syntax(Box::new(SyntaxError {
+        token: token.text.to_string(),
+        span: token.span(),
+        message,
+        suggestion,
+    }))
+}
+
+/// An expression tree, built from a postfix program for printing.
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Value(Elt),
+    Var(String),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// How tightly the expression binds, for deciding where it needs
+    /// parentheses.
+    fn prec(&self) -> u8 {
+        match self {
+            Expr::Neg(_) => operator(Op::Neg).unwrap().1,
+            Expr::Binary(op, _, _) => operator(*op).unwrap().1,
+            // A negative number reads like a negation.
+            Expr::Value(val) if val.to_string().starts_with('-') => operator(Op::Neg).unwrap().1,
+            _ => ATOM,
+        }
+    }
+
+    /// Writes `self` as an operand that must bind at least as tightly as
+    /// `prec`, in parentheses if it doesn't.
+    fn fmt_operand(&self, f: &mut fmt::Formatter, prec: u8) -> fmt::Result {
+        if self.prec() < prec {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    /// Writes the expression in infix, with only the parentheses it needs.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Value(val) => write!(f, "{}", val),
+            Expr::Var(name) => write!(f, "{}", name),
+            Expr::Neg(x) => {
+                write!(f, "-")?;
+                x.fmt_operand(f, self.prec())
+            }
+            Expr::Binary(op, x, y) => {
+                let (symbol, prec, assoc) = operator(*op).unwrap();
+                // The operand on the side the operator doesn't group to must
+                // bind more tightly, so `1 - (2 - 3)` keeps its parentheses.
+                let (left, right) = match assoc {
+                    Assoc::Left => (prec, prec + 1),
+                    Assoc::Right => (prec + 1, prec),
+                };
+                x.fmt_operand(f, left)?;
+                write!(f, " {} ", symbol)?;
+                y.fmt_operand(f, right)
+            }
+        }
+    }
+}
+
+/// Builds the expression trees that a postfix program computes, one for each
+/// value it leaves. The program may only push values, fetch variables, use
+/// the operators of the table and shuffle with `dup`, `drop`, `<->` and
+/// `over`; anything else fails with `Error::Syntax`.
+pub fn to_infix(program: &[Node]) -> rpn::Result<Vec<Expr>> {
+    let mut stack: Vec<Expr> = vec![];
+    for node in program {
+        let op = match node {
+            Node::Push(val) => {
+                stack.push(Expr::Value(val.clone()));
+                continue;
+            }
+            Node::Fetch(name) => {
+                stack.push(Expr::Var(name.clone()));
+                continue;
+            }
+            Node::Word(name) => Op::from_name(name),
+            _ => None,
+        };
+        let op = match op {
+            Some(op) if operator(op).is_some() || matches!(op, Op::Dup | Op::Drop | Op::Swap | Op::Over) => op,
+            _ => return Err(no_infix(node)),
+        };
+        let needs = match op {
+            Op::Neg | Op::Dup | Op::Drop => 1,
+            _ => 2,
+        };
+        if stack.len() < needs {
+            return Err(rpn::Error::Underflow(op.name()));
+        }
+        let y = stack.pop().unwrap();
+        match op {
+            Op::Neg => stack.push(Expr::Neg(Box::new(y))),
+            Op::Dup => stack.extend([y.clone(), y]),
+            Op::Drop => {}
+            Op::Swap => {
+                let x = stack.pop().unwrap();
+                stack.extend([y, x]);
+            }
+            Op::Over => {
+                let x = stack.last().unwrap().clone();
+                stack.extend([y, x]);
+            }
+            op => {
+                let x = stack.pop().unwrap();
+                stack.push(Expr::Binary(op, Box::new(x), Box::new(y)));
+            }
+        }
+    }
+    Ok(stack)
+}
+
+fn no_infix(node: &Node) -> rpn::Error {
+    // Name a control structure by the word it starts with.
+    let text = node.to_string();
+    let name = text.split(' ').next().unwrap_or_default();
+    let token = Token { text: name, start: 0 };
+    error(token, format!("`{}` has no infix form", name), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::words::Dictionary;
+
+    fn postfix(src: &str) -> String {
+        Block(&to_postfix(src, 0).unwrap()).to_string().trim_end().to_string()
+    }
+
+    fn infix(src: &str) -> String {
+        let program = parse(src, &Dictionary::new()).unwrap();
+        let exprs: Vec<String> = to_infix(&program).unwrap().iter().map(|e| e.to_string()).collect();
+        exprs.join(", ")
+    }
+
+    #[test]
+    fn test_to_postfix() {
+        assert_eq!(postfix("(3 + 4) * 2"), "3 4 + 2 *");
+        assert_eq!(postfix("1 - 2 - 3"), "1 2 - 3 -");
+        assert_eq!(postfix("2 ^ 3 ^ 2"), "2 3 2 ^ ^");
+        assert_eq!(postfix("-2 ^ 2"), "2 2 ^ ~");
+        assert_eq!(postfix("x * -(y+1.5) <= 1e-3"), "@x @y 1.5 + ~ * 0.001 <=");
+        assert_eq!(postfix("1 + 2 * 3 = 7"), "1 2 3 * + 7 =");
+    }
+
+    #[test]
+    fn test_to_postfix_errors() {
+        let span = |src: &str| match to_postfix(src, 2) {
+            Err(rpn::Error::Syntax(err)) => err.span,
+            res => panic!("expected a syntax error, got {:?}", res),
+        };
+        assert_eq!(span("(1 + 2"), 2..3);
+        assert_eq!(span("1 + 2)"), 7..8);
+        assert_eq!(span("1 2"), 4..5);
+        assert_eq!(span("1 +"), 5..5);
+        assert_eq!(span("1 $ 2"), 4..5);
+        assert_eq!(span("1 + 2x"), 6..8);
+    }
+
+    #[test]
+    fn test_to_infix() {
+        assert_eq!(infix("3 4 + 2 *"), "(3 + 4) * 2");
+        assert_eq!(infix("1 2 3 - -"), "1 - (2 - 3)");
+        assert_eq!(infix("1 2 - 3 -"), "1 - 2 - 3");
+        assert_eq!(infix("2 3 ^ 2 ^"), "(2 ^ 3) ^ 2");
+        assert_eq!(infix("2 2 ^ ~ -2 2 ^"), "-2 ^ 2, (-2) ^ 2");
+        assert_eq!(infix("@x dup * 1 <-> over"), "1, x * x, 1");
+
+        let words = Dictionary::new();
+        let res = to_infix(&parse("1 +", &words).unwrap());
+        assert!(matches!(res, Err(rpn::Error::Underflow("+"))));
+        for (src, name) in [("1 2 3 rot", "rot"), ("true if 1 then", "if")] {
+            match to_infix(&parse(src, &words).unwrap()) {
+                Err(rpn::Error::Syntax(err)) => assert_eq!(err.token, name),
+                res => panic!("expected a syntax error, got {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for src in ["(3 + 4) * 2", "1 - (2 - 3)", "-x ^ 2 % 3", "a < b = c >= -1", "(a = b) < c"] {
+            let exprs = to_infix(&to_postfix(src, 0).unwrap()).unwrap();
+            assert_eq!(exprs[0].to_string(), src);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::rc::Rc;
+
+use crate::history::Snapshot;
+use crate::parser::{check_word_name, evaluate_limited};
+use crate::rpn::{self, Elt, Stack};
+use crate::words::Dictionary;
+
+/// A word written in Rust by a program that embeds the calculator. Closures
+/// that take the stack are native words.
+pub trait NativeOp {
+    /// Runs the word on the stack. A word that needs more values than the
+    /// stack holds should fail with `Error::Underflow`, and one that can't
+    /// handle a value with `Error::Type`, `Error::Domain` or `Error::Native`.
+    fn call(&self, stack: &mut Stack) -> rpn::Result<()>;
+}
+
+impl<F: Fn(&mut Stack) -> rpn::Result<()>> NativeOp for F {
+    fn call(&self, stack: &mut Stack) -> rpn::Result<()> {
+        self(stack)
+    }
+}
+
+/// Bounds on the work a single call to `Interpreter::eval` may do once the
+/// program runs, so that one that loops forever or piles up values stops with
+/// `Error::Limit`.
+///
+/// These don't make any program safe to run. Parsing comes first and isn't
+/// limited, beyond blocks nesting at most `parser::MAX_NESTING` deep, and
+/// neither is the size of the values a program builds, like the strings and
+/// lists that `concat` makes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+    /// The most steps a program may take, or `None` for no limit. Programs
+    /// are compiled before they run, and each instruction, each node of the
+    /// body of a word that runs and each turn of a `do` loop are steps.
+    /// Constant expressions are folded, so `2 3 +` is a single step.
+    pub max_steps: Option<usize>,
+    /// The most values the stack may hold, or `None` for no limit.
+    pub max_depth: Option<usize>,
+}
+
+/// The result of a program that ran to the end, or to `quit`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    /// The values on the stack afterwards, from the bottom up.
+    pub stack: Vec<Elt>,
+    /// How many steps the program took.
+    pub steps: usize,
+    /// Whether the program stopped early with `quit`.
+    pub quit: bool,
+    /// What the program printed, with `words`.
+    pub output: String,
+}
+
+/// An RPN calculator for embedding in other programs.
+///
+/// Programs are evaluated one at a time on a stack and dictionary that last
+/// between them, so one program may leave values or define words for the
+/// next. A program that fails is rolled back, and leaves the interpreter as
+/// it found it.
+#[derive(Debug)]
+pub struct Interpreter {
+    session: Snapshot,
+    limits: Limits,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    /// Creates an interpreter with an empty stack and no limits.
+    pub fn new() -> Interpreter {
+        Interpreter::with_stack(Stack::new())
+    }
+
+    /// Creates an interpreter whose random numbers are generated from `seed`.
+    pub fn with_seed(seed: u64) -> Interpreter {
+        Interpreter::with_stack(Stack::with_seed(seed))
+    }
+
+    /// Creates an interpreter that starts with `stack`.
+    pub fn with_stack(stack: Stack) -> Interpreter {
+        let session = Snapshot { stack, words: Dictionary::new() };
+        Interpreter { session, limits: Limits::default() }
+    }
+
+    /// Limits each later call to `eval`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Defines `name` as a native word, which programs can then call like
+    /// any other word. Fails with `Error::Syntax` if `name` couldn't be
+    /// written in a program as a word.
+    pub fn register(&mut self, name: &str, op: impl NativeOp + 'static) -> rpn::Result<()> {
+        check_word_name(name)?;
+        self.session.words.define_native(name, Rc::new(op));
+        Ok(())
+    }
+
+    /// Evaluates a program. On success, returns the stack afterwards;
+    /// otherwise the stack and dictionary are rolled back, and the error
+    /// says what went wrong.
+    pub fn eval(&mut self, src: &str) -> rpn::Result<Outcome> {
+        let before = self.session.clone();
+        let mut output = vec![];
+        let (res, steps) =
+            evaluate_limited(&mut self.session.stack, &mut self.session.words, src, self.limits, &mut output);
+        let quit = match res {
+            Ok(()) => false,
+            // Like a script, a program that quits keeps what it did.
+            Err(rpn::Error::Quit) => true,
+            Err(err) => {
+                self.session = before;
+                return Err(err);
+            }
+        };
+        let output = String::from_utf8_lossy(&output).into_owned();
+        Ok(Outcome { stack: self.stack().to_vec(), steps, quit, output })
+    }
+
+    /// Pushes a value for the next program to use.
+    pub fn push(&mut self, val: Elt) {
+        self.session.stack.push(val).expect("Push failed");
+    }
+
+    /// The values on the stack, from the bottom up.
+    pub fn stack(&self) -> &[Elt] {
+        self.session.stack.as_slice()
+    }
+
+    /// The words and variables defined so far.
+    pub fn words(&self) -> &Dictionary {
+        &self.session.words
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpn::Error;
+
+    #[test]
+    fn test_eval() {
+        let mut interp = Interpreter::new();
+        let outcome = interp.eval(": sq dup * ; 3 sq").unwrap();
+        assert_eq!(outcome.stack, vec![Elt::Int(9)]);
+        assert_eq!(outcome.steps, 5);
+        assert!(!outcome.quit);
+
+        interp.push(Elt::Int(1));
+        let outcome = interp.eval("+ sq quit 5").unwrap();
+        assert_eq!(outcome.stack, vec![Elt::Int(100)]);
+        assert!(outcome.quit);
+    }
+
+    #[test]
+    fn test_eval_output() {
+        let mut interp = Interpreter::new();
+        interp.register("half", |_: &mut Stack| Ok(())).unwrap();
+        let outcome = interp.eval(": sq dup * ; words").unwrap();
+        assert!(outcome.output.starts_with("sq half words apply"));
+        assert!(outcome.output.ends_with('\n'));
+        assert_eq!(interp.eval("1").unwrap().output, "");
+    }
+
+    #[test]
+    fn test_eval_rolls_back() {
+        let mut interp = Interpreter::new();
+        interp.eval("1 2 3 !x").unwrap();
+        assert!(matches!(interp.eval(": x ; 5 !x drop drop drop"), Err(Error::Underflow("drop"))));
+        assert_eq!(interp.stack(), &[Elt::Int(1), Elt::Int(2)]);
+        assert_eq!(interp.words().fetch("x"), Some(&Elt::Int(3)));
+        assert!(interp.words().get("x").is_none());
+    }
+
+    #[test]
+    fn test_register() {
+        let mut interp = Interpreter::new();
+        interp
+            .register("half", |stack: &mut Stack| match stack.pop()? {
+                Elt::Int(x) if x % 2 == 0 => stack.push(Elt::Int(x / 2)),
+                Elt::Int(x) => Err(Error::Native(format!("{} is odd", x))),
+                _ => Err(Error::Type),
+            })
+            .unwrap();
+        assert_eq!(interp.eval("12 half half").unwrap().stack, vec![Elt::Int(3)]);
+        assert!(matches!(interp.eval("half"), Err(Error::Native(msg)) if msg == "3 is odd"));
+
+        // A user-defined word takes precedence.
+        assert_eq!(interp.eval(": half 1 ; half").unwrap().stack, vec![Elt::Int(3), Elt::Int(1)]);
+
+        for name in ["", "if", "12", "a b", "!x", "[x"] {
+            assert!(matches!(interp.register(name, |_: &mut Stack| Ok(())), Err(Error::Syntax(_))));
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits { max_steps: Some(100), max_depth: None });
+        assert!(matches!(interp.eval("begin false until"), Err(Error::Limit("steps"))));
+        assert!(matches!(interp.eval("1000 0 do loop"), Err(Error::Limit("steps"))));
+        assert_eq!(interp.eval("10 0 do loop").unwrap().steps, 13);
+
+        interp.set_limits(Limits { max_steps: None, max_depth: Some(3) });
+        assert!(interp.eval("1 2 3").is_ok());
+        assert!(matches!(interp.eval("{ 4 5 } [ ] each"), Err(Error::Limit("stack"))));
+        assert_eq!(interp.stack().len(), 3);
+
+        // Steps are counted in the compiled program, and in the words it calls.
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits { max_steps: Some(100), max_depth: None });
+        assert_eq!(interp.eval("2 3 + 4 *").unwrap().steps, 1);
+        assert!(matches!(interp.eval(": f begin false until ; f"), Err(Error::Limit("steps"))));
+    }
+}
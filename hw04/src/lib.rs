@@ -0,0 +1,18 @@
+//! An RPN calculator, which `main` runs as a REPL or on scripts, and which
+//! other programs can embed as a small scripting language through
+//! `Interpreter`.
+
+pub mod ast;
+pub mod compiler;
+pub mod debugger;
+mod editor;
+pub mod history;
+pub mod infix;
+pub mod interpreter;
+pub mod parser;
+pub mod ratio;
+pub mod rpn;
+pub mod units;
+pub mod words;
+
+pub use interpreter::{Interpreter, Limits, NativeOp, Outcome};
@@ -1,10 +1,100 @@
-pub mod parser;
-pub mod rpn;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::process;
 
-use parser::read_eval_print_loop;
+use hw04::debugger::{Observer, Tracer};
+use hw04::infix::{self, Notation};
+use hw04::parser::{evaluate_script, read_eval_print_loop};
+use hw04::rpn::{self, Stack};
 
+const USAGE: &str = "usage: hw04 [--seed N] [--infix] [--trace] [FILE | -e EXPR]";
+
+/// Starts the calculator. With no arguments and a terminal on stdin it runs a
+/// read-eval-print loop, which keeps going after errors. Otherwise it
+/// evaluates a script from a file, from `-e EXPR`, or from piped stdin, prints
+/// the final stack and exits with `Error::exit_code`. `--seed N` makes random
+/// numbers reproducible, and `--infix` reads infix expressions, one to a line,
+/// instead of RPN. `--trace` prints the stack to stderr after each operator
+/// runs.
 fn main() {
-    if let Err(err) = read_eval_print_loop() {
-        println!("Error: {:?}", err);
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let trace = match args.iter().position(|arg| arg == "--trace") {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    };
+    let notation = match args.iter().position(|arg| arg == "--infix") {
+        Some(i) => {
+            args.remove(i);
+            Notation::Infix
+        }
+        None => Notation::Postfix,
+    };
+    let stack = match args.iter().position(|arg| arg == "--seed") {
+        Some(i) => match args.get(i + 1).map(|seed| seed.parse()) {
+            Some(Ok(seed)) => {
+                args.drain(i..i + 2);
+                Stack::with_seed(seed)
+            }
+            _ => usage(),
+        },
+        None => Stack::new(),
+    };
+
+    let script = match args.as_slice() {
+        [] if io::stdin().is_terminal() => {
+            if let Err(err) = read_eval_print_loop(stack, notation, trace) {
+                println!("Error: {:?}", err);
+            }
+            return;
+        }
+        [] => {
+            let mut buf = String::new();
+            io::stdin().read_to_string(&mut buf).map(|_| buf)
+        }
+        [flag, expr] if flag == "-e" => Ok(expr.clone()),
+        [path] if !path.starts_with('-') => fs::read_to_string(path),
+        _ => usage(),
+    };
+
+    let buf = match script {
+        Ok(buf) => buf,
+        Err(err) => {
+            let err = rpn::Error::IO(err);
+            eprintln!("Error: {:?}", err);
+            process::exit(err.exit_code());
+        }
+    };
+    // Errors in the postfix program point into it, not into the infix
+    // source it was translated from.
+    let program = match notation {
+        Notation::Postfix => buf,
+        Notation::Infix => infix::translate(&buf).unwrap_or_else(|err| fail(err, &buf)),
+    };
+    let mut tracer = Tracer::new(io::stderr());
+    let observer: Option<&mut dyn Observer> = if trace { Some(&mut tracer) } else { None };
+    match evaluate_script(stack, &program, observer) {
+        Ok(stack) => {
+            let vals: Vec<String> = stack.as_slice().iter().map(|x| x.to_string()).collect();
+            println!("{}", vals.join(" "));
+        }
+        Err(err) => fail(err, &program),
+    }
+}
+
+/// Reports `err`, showing where in `src` a syntax error is, and exits.
+fn fail(err: rpn::Error, src: &str) -> ! {
+    match &err {
+        rpn::Error::Syntax(syntax) => eprintln!("Syntax error:\n{}", syntax.render(src)),
+        err => eprintln!("Error: {:?}", err),
     }
+    process::exit(err.exit_code());
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
 }
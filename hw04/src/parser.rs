@@ -1,109 +1,1083 @@
-use std::str::FromStr;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
 use std::io::{self, Write};
+use std::ops::Range;
+use std::rc::Rc;
 
+use crate::ast::{parse_number, Node};
+use crate::compiler::{self, Instr};
+use crate::debugger::{Debugger, Observer, Resume, Tracer};
+use crate::editor::LineReader;
+use crate::history::{History, Snapshot};
+use crate::infix::{self, Notation};
+use crate::interpreter::Limits;
+use crate::units::Unit;
 use crate::rpn::{self, Stack};
+use crate::words::{Dictionary, MAX_CALL_DEPTH};
 
-/// Start a read-eval-print loop, which runs until an error or `quit`.
-pub fn read_eval_print_loop() -> rpn::Result<()> {
-    // Create a stack to work on.
-    let mut stack = Stack::new();
+/// Start a read-eval-print loop, which runs until `quit` or the end of
+/// input. An error in a line is reported, and the line is rolled back.
+///
+/// Besides programs, the loop understands a few commands on a line of their
+/// own: `undo` and `redo` step through the history of lines, `history` lists
+/// it, and `.stack` prints the whole stack. `save FILE` writes the stack,
+/// variables and words to a file, and `load FILE` replaces them with the ones
+/// saved there. `.infix PROGRAM` prints the expressions a program computes.
+///
+/// `debug PROGRAM` runs a program under the debugger, pausing before each
+/// node, and `break WORD` sets a breakpoint that pauses any line when it
+/// calls the word. `unbreak WORD` removes it, and `break` lists them.
+///
+/// With `Notation::Infix`, each other line is an infix expression. With
+/// `trace`, the stack is printed to stderr after each operator runs.
+pub fn read_eval_print_loop(stack: Stack, notation: Notation, trace: bool) -> rpn::Result<()> {
+    // Work on the given stack, with a dictionary for the user's words.
+    let mut session = Snapshot { stack, words: Dictionary::new() };
+    let mut history = History::new();
+    let mut reader = LineReader::new()?;
+    let mut breakpoints = BTreeSet::new();
 
     loop {
-        // Print a user input prompt.
-        print!("> ");
-        // try!() is deprecated in Rust 2021.
-        io::stdout().flush().map_err(rpn::Error::IO)?;
-
-        // TODO: Read from stdin into a String, and evaluate_line the result.
-        // * An io::Error should be converted into a rpn::Error::IO
-        let mut buf = String::new();
-        io::stdin().read_line(&mut buf).map_err(rpn::Error::IO)?;
-        evaluate_line(&mut stack, &buf)?;
-        print!("# {:?}\n", stack.last()?);
-    }
-}
-
-fn evaluate_line(stack: &mut Stack, buf: &String) -> rpn::Result<()> {
-    // Create an iterator over the tokens.
-    let tokens = buf.trim().split_whitespace();
-
-    // TODO: Evaluate all of the tokens on the line.
-    for token in tokens {
-        match token {
-            "true" => stack.push(rpn::Elt::Bool(true)),
-            "false" => stack.push(rpn::Elt::Bool(false)),
-            "+" => stack.eval(rpn::Op::Add),
-            "~" => stack.eval(rpn::Op::Neg),
-            "<->" => stack.eval(rpn::Op::Swap),
-            "=" => stack.eval(rpn::Op::Eq),
-            "#" => stack.eval(rpn::Op::Rand),
-            "quit" => stack.eval(rpn::Op::Quit),
-            digits => {
-                match i32::from_str(digits) {
-                    Ok(val) => stack.push(rpn::Elt::Int(val)),
-                    Err(_) => return Err(rpn::Error::Syntax),
-                }
-            }
-        }?;
+        let buf = match reader.read_line("> ", &session.words)? {
+            Some(buf) => buf,
+            None => {
+                // End of input.
+                println!();
+                return Ok(());
+            }
+        };
+
+        let line = buf.trim();
+        let (command, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match (command, arg.trim()) {
+            ("undo", "") => match history.undo(session.clone()) {
+                Some(before) => session = before,
+                None => println!("Nothing to undo."),
+            },
+            ("redo", "") => match history.redo(session.clone()) {
+                Some(after) => session = after,
+                None => println!("Nothing to redo."),
+            },
+            ("history", "") => {
+                for (i, line) in history.lines().enumerate() {
+                    println!("{:4}  {}", i + 1, line);
+                }
+                continue;
+            }
+            (".stack", "") => {
+                print_stack(&session.stack);
+                continue;
+            }
+            ("break", "") => {
+                let words: Vec<&str> = breakpoints.iter().map(String::as_str).collect();
+                println!("Breakpoints: {}", words.join(" "));
+                continue;
+            }
+            ("break", word) => {
+                breakpoints.insert(word.to_string());
+                continue;
+            }
+            ("unbreak", word) if !word.is_empty() => {
+                if !breakpoints.remove(word) {
+                    println!("No breakpoint on {}.", word);
+                }
+                continue;
+            }
+            (".infix", program) => {
+                match parse(program, &session.words).and_then(|program| infix::to_infix(&program)) {
+                    Ok(exprs) => {
+                        let exprs: Vec<String> = exprs.iter().map(|expr| expr.to_string()).collect();
+                        println!("{}", exprs.join(", "));
+                    }
+                    Err(rpn::Error::Syntax(err)) => println!("Syntax error: {}", err),
+                    Err(err) => println!("Error: {:?}", err),
+                }
+                continue;
+            }
+            ("save", path) if !path.is_empty() => {
+                match save(&session, path) {
+                    Ok(()) => println!("Saved to {}.", path),
+                    Err(err) => println!("Error: {:?}", err),
+                }
+                continue;
+            }
+            ("load", path) if !path.is_empty() => match load(&session, path) {
+                Ok(loaded) => history.record(&buf, std::mem::replace(&mut session, loaded)),
+                Err(rpn::Error::Syntax(err)) => {
+                    println!("Syntax error in {}: {}", path, err);
+                    continue;
+                }
+                Err(err) => {
+                    println!("Error: {:?}", err);
+                    continue;
+                }
+            },
+            _ => match run_line(&mut session, &buf, notation, trace, &mut breakpoints) {
+                Ok(before) => {
+                    if !buf.trim().is_empty() {
+                        history.record(&buf, before);
+                    }
+                }
+                Err(rpn::Error::Quit) => return Ok(()),
+                // Line the caret up with the input, after the prompt.
+                Err(rpn::Error::Syntax(err)) => {
+                    println!("  {}", err.caret(&buf));
+                    continue;
+                }
+                Err(err) => {
+                    println!("Error: {:?}", err);
+                    continue;
+                }
+            },
+        }
+        if let Ok(top) = session.stack.last() {
+            println!("# {:?}", top);
+        }
+    }
+}
+
+/// Prints every value on the stack with its type, from the top down.
+fn print_stack(stack: &Stack) {
+    if stack.is_empty() {
+        println!("(empty)");
+    }
+    for (i, val) in stack.as_slice().iter().rev().enumerate() {
+        println!("{:4}: {:<8} {}", i, val.type_name(), val);
+    }
+}
+
+/// Writes the session to `path`, as a program that rebuilds it.
+fn save(session: &Snapshot, path: &str) -> rpn::Result<()> {
+    fs::write(path, session.to_string()).map_err(rpn::Error::IO)
+}
+
+/// Reads a session written by `save` from `path`. The loaded session keeps
+/// the random number generator of `session`, which isn't saved.
+fn load(session: &Snapshot, path: &str) -> rpn::Result<Snapshot> {
+    let buf = fs::read_to_string(path).map_err(rpn::Error::IO)?;
+    let mut stack = session.stack.clone();
+    stack.eval(rpn::Op::Clear)?;
+    let mut loaded = Snapshot { stack, words: Dictionary::new() };
+    evaluate_line(&mut loaded.stack, &mut loaded.words, &buf)?;
+    Ok(loaded)
+}
+
+/// Runs a line of the REPL that isn't a command, under the debugger if it's
+/// `debug PROGRAM` or there are breakpoints, and traced with `trace`. With
+/// both, the line is traced and still stops at breakpoints.
+fn run_line(
+    session: &mut Snapshot,
+    buf: &str,
+    notation: Notation,
+    trace: bool,
+    breakpoints: &mut BTreeSet<String>,
+) -> rpn::Result<Snapshot> {
+    let debug = matches!(buf.trim().split_once(char::is_whitespace), Some(("debug", program)) if !program.trim().is_empty());
+    // Blank out `debug` rather than cut it off, to keep the carets of syntax
+    // errors lined up with the input.
+    let program = if debug { buf.replacen("debug", "     ", 1) } else { buf.to_string() };
+    let resume = if debug { Resume::Step } else { Resume::Continue };
+    let (mut tracer, mut debugger, mut both);
+    let observer: Option<&mut dyn Observer> = match (debug || !breakpoints.is_empty(), trace) {
+        (true, true) => {
+            let debugger = Debugger::new(io::stdin().lock(), io::stdout(), breakpoints, resume);
+            both = (Tracer::new(io::stderr()), debugger);
+            Some(&mut both)
+        }
+        (true, false) => {
+            debugger = Debugger::new(io::stdin().lock(), io::stdout(), breakpoints, resume);
+            Some(&mut debugger)
+        }
+        (false, true) => {
+            tracer = Tracer::new(io::stderr());
+            Some(&mut tracer)
+        }
+        (false, false) => None,
+    };
+    let program = translate_line(&program, notation)?;
+    evaluate_transaction(session, &program, observer)
+}
+
+/// Converts a line in `notation` to a program.
+fn translate_line(buf: &str, notation: Notation) -> rpn::Result<String> {
+    match notation {
+        Notation::Postfix => Ok(buf.to_string()),
+        Notation::Infix => infix::translate(buf),
+    }
+}
+
+/// Evaluates a line as a transaction: if it fails, the stack and the
+/// dictionary are rolled back to how they were before the line. Returns the
+/// state from before the line.
+fn evaluate_transaction(
+    session: &mut Snapshot,
+    buf: &str,
+    observer: Option<&mut dyn Observer>,
+) -> rpn::Result<Snapshot> {
+    let before = session.clone();
+    match evaluate_with(&mut session.stack, &mut session.words, buf, observer) {
+        Ok(()) => Ok(before),
+        Err(err) => {
+            *session = before;
+            Err(err)
+        }
+    }
+}
+
+/// Evaluates a whole script on `stack` without prompts, and returns the final
+/// stack. Newlines are whitespace, so definitions and control structures may
+/// span lines. `quit` ends the script early. An `observer`, if given, watches
+/// it run.
+pub fn evaluate_script(mut stack: Stack, buf: &str, observer: Option<&mut dyn Observer>) -> rpn::Result<Stack> {
+    let mut words = Dictionary::new();
+    match evaluate_with(&mut stack, &mut words, buf, observer) {
+        Ok(()) | Err(rpn::Error::Quit) => Ok(stack),
+        Err(err) => Err(err),
+    }
+}
+
+/// Evaluates a line of tokens. A line may define new words with
+/// `: name body ;`, which are added to `words`.
+///
+/// The whole line is parsed before any of it runs, so a line with unbalanced
+/// control words or unknown words fails with `Error::Syntax` and leaves the
+/// stack alone. It's then compiled to bytecode, which is what runs.
+fn evaluate_line(stack: &mut Stack, words: &mut Dictionary, buf: &str) -> rpn::Result<()> {
+    let program = parse(buf, words)?;
+    let code = compiler::compile(&program, words);
+    run_compiled(stack, words, &code)
+}
+
+/// Evaluates a line like `evaluate_line`, but walks the parsed program rather
+/// than compiling it, so that `observer` sees every node and operator.
+pub fn evaluate_observed(
+    stack: &mut Stack,
+    words: &mut Dictionary,
+    buf: &str,
+    observer: &mut dyn Observer,
+) -> rpn::Result<()> {
+    let program = parse(buf, words)?;
+    let mut out = io::stdout();
+    let mut machine = Machine {
+        stack, words, loops: vec![], limits: Limits::default(), steps: 0, observer: Some(observer), out: &mut out,
+    };
+    machine.run(&program, 0)
+}
+
+fn evaluate_with(
+    stack: &mut Stack,
+    words: &mut Dictionary,
+    buf: &str,
+    observer: Option<&mut dyn Observer>,
+) -> rpn::Result<()> {
+    match observer {
+        Some(observer) => evaluate_observed(stack, words, buf, observer),
+        None => evaluate_line(stack, words, buf),
+    }
+}
+
+/// Runs a parsed program by walking its tree, with no limits.
+pub fn run_parsed(stack: &mut Stack, words: &mut Dictionary, program: &[Node]) -> rpn::Result<()> {
+    let mut out = io::stdout();
+    let mut machine =
+        Machine { stack, words, loops: vec![], limits: Limits::default(), steps: 0, observer: None, out: &mut out };
+    machine.run(program, 0)
+}
+
+/// Runs a program compiled by `compiler::compile`, with no limits.
+pub fn run_compiled(stack: &mut Stack, words: &mut Dictionary, code: &[Instr]) -> rpn::Result<()> {
+    let mut out = io::stdout();
+    let mut machine =
+        Machine { stack, words, loops: vec![], limits: Limits::default(), steps: 0, observer: None, out: &mut out };
+    machine.run_code(code, 0)
+}
+
+/// Evaluates a line like `evaluate_line`, compiling it and running the
+/// bytecode, but fails with `Error::Limit` if it goes beyond `limits`, and
+/// writes what `words` prints to `out`. Returns the result with the number of
+/// steps the line took, which are counted even if it failed.
+pub fn evaluate_limited(
+    stack: &mut Stack,
+    words: &mut Dictionary,
+    buf: &str,
+    limits: Limits,
+    out: &mut dyn Write,
+) -> (rpn::Result<()>, usize) {
+    let code = match parse(buf, words) {
+        Ok(program) => compiler::compile(&program, words),
+        Err(err) => return (Err(err), 0),
+    };
+    let mut machine = Machine { stack, words, loops: vec![], limits, steps: 0, observer: None, out };
+    // The last step may have filled the stack.
+    let res = machine.run_code(&code, 0).and_then(|()| machine.check_depth());
+    (res, machine.steps)
+}
+
+/// A token of input, and the byte offset it starts at.
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    start: usize,
+}
+
+impl Token<'_> {
+    fn span(&self) -> Range<usize> {
+        self.start..self.start + self.text.len()
+    }
+}
+
+/// Splits a line into tokens, keeping their offsets. Tokens are separated by
+/// whitespace, except that brackets are always tokens of their own and a
+/// string literal runs to its closing quote.
+fn tokenize(buf: &str) -> rpn::Result<Vec<Token<'_>>> {
+    let mut tokens = vec![];
+    let mut chars = buf.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if is_bracket(c) {
+            chars.next();
+            tokens.push(Token { text: &buf[start..start + 1], start });
+        } else if c == '"' {
+            chars.next();
+            let mut escaped = false;
+            let end = chars.find(|&(_, c)| {
+                let end = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                end
+            });
+            match end {
+                Some((end, _)) => tokens.push(Token { text: &buf[start..end + 1], start }),
+                None => {
+                    let token = Token { text: "\"", start };
+                    return Err(syntax(token, "this string is never closed".to_string(), None));
+                }
+            }
+        } else {
+            let mut end = buf.len();
+            while let Some(&(i, c)) = chars.peek() {
+                if c.is_whitespace() || is_bracket(c) {
+                    end = i;
+                    break;
+                }
+                chars.next();
+            }
+            tokens.push(Token { text: &buf[start..end], start });
+        }
+    }
+    Ok(tokens)
+}
+
+/// Whether `text` is a word that ends the block it's in.
+fn closes_block(text: &str) -> bool {
+    matches!(text, "else" | "then" | "until" | "loop" | ";" | "]" | "}")
+}
+
+fn is_bracket(c: char) -> bool {
+    matches!(c, '[' | ']' | '{' | '}')
+}
+
+/// Decodes a string literal token, with its quotes, into its contents.
+fn parse_string(token: Token) -> rpn::Result<String> {
+    let mut s = String::new();
+    let mut chars = token.text[1..token.text.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => s.push('\n'),
+            Some('t') => s.push('\t'),
+            Some(c @ ('"' | '\\')) => s.push(c),
+            _ => {
+                let message = "strings only have the escapes \\n, \\t, \\\" and \\\\".to_string();
+                return Err(syntax(token, message, None));
+            }
+        }
+    }
+    Ok(s)
+}
+
+/// How deeply quotations, lists and control structures may nest. The parser
+/// and the code that runs or prints a program recurse into nested blocks, so
+/// without a limit a line of 50000 `[`s would overflow the stack.
+pub const MAX_NESTING: usize = 256;
+
+/// Parses a line into a program. Every word in it must already be in
+/// `words`, be defined earlier in the line, or be a builtin.
+pub fn parse(buf: &str, words: &Dictionary) -> rpn::Result<Vec<Node>> {
+    let tokens = tokenize(buf)?;
+    let mut parser = Parser { tokens: tokens.into_iter(), words, defined: HashSet::new(), src: buf };
+
+    match parser.parse_block(0)? {
+        (program, None) => Ok(program),
+        (_, Some(token)) => Err(unopened(token)),
+    }
+}
+
+/// Reads tokens into a program.
+struct Parser<'a, 'w> {
+    tokens: std::vec::IntoIter<Token<'a>>,
+    words: &'w Dictionary,
+    /// Words defined so far on this line, which later tokens may call.
+    defined: HashSet<&'a str>,
+    /// The whole input, which `infix` reads its expression from.
+    src: &'a str,
+}
+
+impl<'a> Parser<'a, '_> {
+    /// Parses nodes up to the control word that ends the current block, and
+    /// returns them with that word, or `None` at the end of the line. The
+    /// block is nested `depth` deep, and definitions are only allowed at the
+    /// top, where `depth` is 0.
+    fn parse_block(&mut self, depth: usize) -> rpn::Result<(Vec<Node>, Option<Token<'a>>)> {
+        let mut nodes = vec![];
+        while let Some(token) = self.tokens.next() {
+            let node = match token.text {
+                text if closes_block(text) => return Ok((nodes, Some(token))),
+                "[" => {
+                    let (body, _) = self.parse_body(token, &["]"], depth + 1)?;
+                    Node::Push(rpn::Elt::Quote(Rc::new(body)))
+                }
+                "{" => Node::Push(self.parse_list(token, depth + 1)?),
+                "if" => {
+                    let (then, end) = self.parse_body(token, &["then", "else"], depth + 1)?;
+                    if end.text == "else" {
+                        let (els, _) = self.parse_body(token, &["then"], depth + 1)?;
+                        Node::If(then, els)
+                    } else {
+                        Node::If(then, vec![])
+                    }
+                }
+                "begin" => Node::Until(self.parse_body(token, &["until"], depth + 1)?.0),
+                "do" => Node::Loop(self.parse_body(token, &["loop"], depth + 1)?.0),
+                ":" if depth == 0 => {
+                    let name = self.tokens.next().ok_or_else(|| {
+                        self.error_at_end("`:` needs a name for the new word")
+                    })?;
+                    check_name(name)?;
+                    // The word may call itself.
+                    self.defined.insert(name.text);
+                    let (body, _) = self.parse_body(token, &[";"], depth + 1)?;
+                    Node::Define(name.text.to_string(), body)
+                }
+                ":" => return Err(syntax(token, "definitions can't nest".to_string(), None)),
+                "infix" => {
+                    nodes.extend(self.parse_infix(token)?);
+                    continue;
+                }
+                "to" => {
+                    let unit = self.tokens.next().ok_or_else(|| self.error_at_end("`to` needs a unit"))?;
+                    match Unit::parse(unit.text) {
+                        Some(unit) => Node::Convert(unit),
+                        None => {
+                            let message = format!("`{}` isn't a unit", unit.text);
+                            return Err(syntax(unit, message, None));
+                        }
+                    }
+                }
+                "i" => Node::LoopIndex(0),
+                "j" => Node::LoopIndex(1),
+                "true" => Node::Push(rpn::Elt::Bool(true)),
+                "false" => Node::Push(rpn::Elt::Bool(false)),
+                text if text.starts_with('"') => Node::Push(rpn::Elt::Str(parse_string(token)?)),
+                text if text.len() > 1 && text.starts_with('!') => Node::Store(text[1..].to_string()),
+                text if text.len() > 1 && text.starts_with('@') => Node::Fetch(text[1..].to_string()),
+                text => match parse_number(text) {
+                    Some(val) => Node::Push(val),
+                    None if self.is_word(text) => Node::Word(text.to_string()),
+                    None => return Err(self.unknown(token)),
+                },
+            };
+            nodes.push(node);
+        }
+        Ok((nodes, None))
+    }
+
+    /// Parses the infix expression after `infix`, and skips the tokens in it.
+    /// The expression runs to the end of the line, or to the first word that
+    /// closes a block, like `;` or `]`, which is left to close its block.
+    fn parse_infix(&mut self, infix: Token<'a>) -> rpn::Result<Vec<Node>> {
+        let start = infix.span().end;
+        let mut end = self.src[start..].find('\n').map_or(self.src.len(), |i| start + i);
+        while let Some(&token) = self.tokens.as_slice().first().filter(|token| token.start < end) {
+            if closes_block(token.text) {
+                end = token.start;
+                break;
+            }
+            self.tokens.next();
+        }
+        infix::to_postfix(&self.src[start..end], start)
+    }
+
+    /// Parses the rest of a list literal opened by `open`. A list holds only
+    /// values: numbers, booleans, strings, quotations and other lists. The
+    /// list is nested `depth` deep.
+    fn parse_list(&mut self, open: Token<'a>, depth: usize) -> rpn::Result<rpn::Elt> {
+        check_nesting(open, depth)?;
+        let mut vals = vec![];
+        while let Some(token) = self.tokens.next() {
+            let val = match token.text {
+                "}" => return Ok(rpn::Elt::List(vals)),
+                "{" => self.parse_list(token, depth + 1)?,
+                "[" => rpn::Elt::Quote(Rc::new(self.parse_body(token, &["]"], depth + 1)?.0)),
+                "true" => rpn::Elt::Bool(true),
+                "false" => rpn::Elt::Bool(false),
+                text if text.starts_with('"') => rpn::Elt::Str(parse_string(token)?),
+                text => match parse_number(text) {
+                    Some(val) => val,
+                    None => {
+                        let message = format!("`{}` isn't a value, so it can't be in a list", text);
+                        let suggestion = "quote code as `[ ... ]`".to_string();
+                        return Err(syntax(token, message, Some(suggestion)));
+                    }
+                },
+            };
+            vals.push(val);
+        }
+        let message = "`{` is never closed".to_string();
+        Err(syntax(open, message, Some("add `}`".to_string())))
+    }
+
+    /// Parses the body of the control structure opened by `open`, which must
+    /// be closed by one of `ends`, and is nested `depth` deep. Returns the
+    /// body and the closing token.
+    fn parse_body(&mut self, open: Token<'a>, ends: &[&str], depth: usize) -> rpn::Result<(Vec<Node>, Token<'a>)> {
+        check_nesting(open, depth)?;
+        match self.parse_block(depth)? {
+            (body, Some(end)) if ends.contains(&end.text) => Ok((body, end)),
+            (_, Some(end)) => {
+                let message = format!("`{}` can't close `{}`", end.text, open.text);
+                let suggestion = format!("did you mean `{}`?", ends[0]);
+                Err(syntax(end, message, Some(suggestion)))
+            }
+            (_, None) => {
+                let message = format!("`{}` is never closed", open.text);
+                let suggestion = format!("add `{}`", ends[0]);
+                Err(syntax(open, message, Some(suggestion)))
+            }
+        }
+    }
+
+    /// Whether `text` names a word that will exist when it runs.
+    fn is_word(&self, text: &str) -> bool {
+        MACHINE_WORDS.contains(&text)
+            || self.defined.contains(text)
+            || self.words.get(text).is_some()
+            || self.words.native(text).is_some()
+            || rpn::Op::from_name(text).is_some()
+    }
+
+    /// Whether `text` is a complete token on its own.
+    fn is_token(&self, text: &str) -> bool {
+        is_reserved(text) || parse_number(text).is_some() || self.is_word(text)
+    }
+
+    /// Reports an unknown word, suggesting how it might have been meant.
+    fn unknown(&self, token: Token) -> rpn::Error {
+        let message = format!("unknown word `{}`", token.text);
+        let suggestion = self
+            .split(token.text)
+            .map(|parts| parts.join(" "))
+            .or_else(|| self.closest(token.text))
+            .map(|fix| format!("did you mean `{}`?", fix));
+        syntax(token, message, suggestion)
+    }
+
+    /// Splits a token that's missing spaces into as few known tokens as
+    /// possible, as in `~false`.
+    fn split<'t>(&self, text: &'t str) -> Option<Vec<&'t str>> {
+        // best[i] is the shortest split of text[..i], if there is one.
+        let bounds: Vec<usize> = text.char_indices().map(|(i, _)| i).chain([text.len()]).collect();
+        let mut best: Vec<Option<Vec<&str>>> = vec![None; text.len() + 1];
+        best[0] = Some(vec![]);
+        for &end in &bounds[1..] {
+            for &start in bounds.iter().take_while(|&&start| start < end) {
+                let part = &text[start..end];
+                let Some(prefix) = &best[start] else { continue };
+                let shorter = best[end].as_ref().is_none_or(|b| b.len() > prefix.len() + 1);
+                if shorter && self.is_token(part) {
+                    let mut parts = prefix.clone();
+                    parts.push(part);
+                    best[end] = Some(parts);
+                }
+            }
+        }
+        best.pop().flatten().filter(|parts| parts.len() > 1)
+    }
+
+    /// Finds the known word closest to a misspelled one, if any is close.
+    fn closest(&self, text: &str) -> Option<String> {
+        let known = self.words.names().into_iter()
+            .chain(self.words.native_names())
+            .chain(self.defined.iter().copied());
+        known
+            .chain(builtin_words())
+            .map(|word| (edit_distance(text, word), word))
+            .filter(|&(dist, word)| dist <= 2 && dist < word.chars().count())
+            .min()
+            .map(|(_, word)| word.to_string())
+    }
+
+    fn error_at_end(&self, message: &str) -> rpn::Error {
+        let token = Token { text: "", start: self.src.len() };
+        syntax(token, message.to_string(), None)
+    }
+}
+
+/// Checks that `name` can be the name of a word defined outside a program:
+/// it must be a single token, and not a number or a word with its own meaning
+/// to the parser.
+pub fn check_word_name(name: &str) -> rpn::Result<()> {
+    check_name(Token { text: name, start: 0 })
+}
+
+fn check_name(name: Token) -> rpn::Result<()> {
+    let split = name.text.is_empty() || name.text.contains(|c: char| c.is_whitespace() || is_bracket(c));
+    if split || is_reserved(name.text) || parse_number(name.text).is_some() {
+        let message = format!("`{}` can't be the name of a word", name.text);
+        return Err(syntax(name, message, None));
+    }
+    Ok(())
+}
+
+/// Fails if a block opened by `open`, nested `depth` deep, is too deep.
+fn check_nesting(open: Token, depth: usize) -> rpn::Result<()> {
+    if depth > MAX_NESTING {
+        let message = format!("blocks can't nest more than {} deep", MAX_NESTING);
+        return Err(syntax(open, message, None));
     }
     Ok(())
 }
 
+fn syntax(token: Token, message: String, suggestion: Option<String>) -> rpn::Error {
+    rpn::Error::Syntax(Box::new(rpn::SyntaxError {
+        token: token.text.to_string(),
+        span: token.span(),
+        message,
+        suggestion,
+    }))
+}
+
+/// Reports a word that isn't defined when it runs.
+fn unknown_word(name: &str) -> rpn::Error {
+    let token = Token { text: name, start: 0 };
+    syntax(token, format!("unknown word `{}`", name), None)
+}
+
+/// Reports a control word that closes a block that was never opened.
+fn unopened(token: Token) -> rpn::Error {
+    let opener = match token.text {
+        "else" | "then" => "if",
+        "until" => "begin",
+        "loop" => "do",
+        "]" => "[",
+        "}" => "{",
+        _ => ":",
+    };
+    let message = format!("`{}` without a matching `{}`", token.text, opener);
+    syntax(token, message, None)
+}
+
+/// The number of single-character edits that turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Whether `name` is part of the language, and so can't name a word.
+fn is_reserved(name: &str) -> bool {
+    matches!(
+        name,
+        ":" | ";" | "if" | "else" | "then" | "begin" | "until" | "do" | "loop"
+            | "i" | "j" | "true" | "false" | "[" | "]" | "{" | "}" | "infix" | "to"
+    ) || name.starts_with(['"', '!', '@'])
+}
+
+/// Every builtin word, for suggestions and completion: the machine's words,
+/// the operators, and the literal and control words.
+pub fn builtin_words() -> Vec<&'static str> {
+    MACHINE_WORDS
+        .into_iter()
+        .chain(rpn::Op::ALL.iter().map(|op| op.name()))
+        .chain(["true", "false", "if", "else", "then", "begin", "until", "do", "loop", "infix", "to"])
+        .collect()
+}
+
+/// The commands the REPL understands as the first word of a line, besides
+/// programs.
+pub const REPL_COMMANDS: [&str; 10] = [
+    "undo", "redo", "history", ".stack", ".infix", "save", "load", "debug", "break", "unbreak",
+];
+
+/// Words that the machine runs itself, rather than the stack, because they
+/// print or run code:
+///
+/// * `words` lists every known word.
+/// * `apply` pops a quotation and runs it.
+/// * `each` pops a quotation and a list, and runs the quotation on each
+///   element of the list in turn.
+/// * `map` pops a quotation and a list, and pushes the list of the values the
+///   quotation leaves for each element.
+/// * `fold` pops a quotation, an initial value and a list, and runs the
+///   quotation on the value and each element in turn, leaving the result.
+const MACHINE_WORDS: [&str; 5] = ["words", "apply", "each", "map", "fold"];
+
+/// The state of a running program.
+struct Machine<'a> {
+    stack: &'a mut Stack,
+    words: &'a mut Dictionary,
+    /// The indices of the running `do` loops, innermost last.
+    loops: Vec<i32>,
+    limits: Limits,
+    /// How many nodes and loop iterations have run.
+    steps: usize,
+    observer: Option<&'a mut dyn Observer>,
+    /// Where `words` prints.
+    out: &'a mut dyn Write,
+}
+
+impl Machine<'_> {
+    /// Runs a program, `depth` user-defined words deep.
+    fn run(&mut self, program: &[Node], depth: usize) -> rpn::Result<()> {
+        for node in program {
+            self.step()?;
+            if let Some(observer) = &mut self.observer {
+                observer.before(node, self.stack, depth)?;
+            }
+            self.run_node(node, depth)?;
+        }
+        Ok(())
+    }
+
+    /// Counts a step, failing if the program has run out of them or has
+    /// filled the stack.
+    fn step(&mut self) -> rpn::Result<()> {
+        self.steps += 1;
+        if self.limits.max_steps.is_some_and(|max| self.steps > max) {
+            return Err(rpn::Error::Limit("steps"));
+        }
+        self.check_depth()
+    }
+
+    fn check_depth(&self) -> rpn::Result<()> {
+        if self.limits.max_depth.is_some_and(|max| self.stack.len() > max) {
+            return Err(rpn::Error::Limit("stack"));
+        }
+        Ok(())
+    }
+
+    fn run_node(&mut self, node: &Node, depth: usize) -> rpn::Result<()> {
+        match node {
+            Node::Push(val) => self.stack.push(val.clone()),
+            Node::Word(name) => self.run_word(name, depth),
+            Node::LoopIndex(n) => self.loop_index(*n),
+            Node::If(then, els) => {
+                if self.pop_bool()? {
+                    self.run(then, depth)
+                } else {
+                    self.run(els, depth)
+                }
+            }
+            Node::Until(body) => loop {
+                self.run(body, depth)?;
+                if self.pop_bool()? {
+                    return Ok(());
+                }
+            },
+            Node::Loop(body) => self.run_loop(body, depth),
+            Node::Define(name, body) => {
+                self.words.define(name, body.clone());
+                Ok(())
+            }
+            Node::Store(name) => self.store(name),
+            Node::Fetch(name) => self.fetch(name),
+            Node::Convert(unit) => self.convert(unit),
+        }
+    }
+
+    /// Runs compiled code, `depth` user-defined words deep. Each instruction
+    /// but a `Need` check is a step, as is each turn of a loop.
+    fn run_code(&mut self, code: &[Instr], depth: usize) -> rpn::Result<()> {
+        for instr in code {
+            if !matches!(instr, Instr::Need(..)) {
+                self.step()?;
+            }
+            match instr {
+                Instr::Push(val) => self.stack.push(val.clone())?,
+                Instr::Op(op) => self.stack.eval(*op)?,
+                Instr::Need(n, name) => {
+                    if self.stack.len() < *n {
+                        return Err(rpn::Error::Underflow(name));
+                    }
+                }
+                Instr::If(then, els) => {
+                    if self.pop_bool()? {
+                        self.run_code(then, depth)?;
+                    } else {
+                        self.run_code(els, depth)?;
+                    }
+                }
+                Instr::Until(body) => loop {
+                    self.run_code(body, depth)?;
+                    if self.pop_bool()? {
+                        break;
+                    }
+                },
+                Instr::Loop(body) => {
+                    let start = self.pop_int()?;
+                    let limit = self.pop_int()?;
+                    for i in start..limit {
+                        self.step()?;
+                        self.loops.push(i);
+                        let res = self.run_code(body, depth);
+                        self.loops.pop();
+                        res?;
+                    }
+                }
+                Instr::Node(node) => self.run_node(node, depth)?,
+            }
+        }
+        Ok(())
+    }
+
+    // The nodes below are run out of line, to keep the frame of `run_node`
+    // small, since recursive words nest it.
+
+    fn loop_index(&mut self, n: usize) -> rpn::Result<()> {
+        let name = if n == 0 { "i" } else { "j" };
+        let i = self.loops.iter().rev().nth(n).ok_or(rpn::Error::Underflow(name))?;
+        self.stack.push(rpn::Elt::Int(*i))
+    }
+
+    fn run_loop(&mut self, body: &[Node], depth: usize) -> rpn::Result<()> {
+        let start = self.pop_int()?;
+        let limit = self.pop_int()?;
+        for i in start..limit {
+            // An empty body still takes a step, so that it can't run forever.
+            self.step()?;
+            self.loops.push(i);
+            let res = self.run(body, depth);
+            self.loops.pop();
+            res?;
+        }
+        Ok(())
+    }
+
+    fn store(&mut self, name: &str) -> rpn::Result<()> {
+        let val = self.stack.pop().map_err(|_| rpn::Error::Underflow("!"))?;
+        self.words.store(name, val);
+        Ok(())
+    }
+
+    fn convert(&mut self, unit: &Unit) -> rpn::Result<()> {
+        let val = self.stack.pop().map_err(|_| rpn::Error::Underflow("to"))?;
+        self.stack.push(val.convert(unit)?)
+    }
+
+    fn fetch(&mut self, name: &str) -> rpn::Result<()> {
+        match self.words.fetch(name) {
+            Some(val) => self.stack.push(val.clone()),
+            None => Err(rpn::Error::Unset(name.to_string())),
+        }
+    }
+
+    /// Runs a word. User-defined words take precedence, so they can replace
+    /// the builtins.
+    fn run_word(&mut self, name: &str, depth: usize) -> rpn::Result<()> {
+        if let Some(body) = self.words.get(name) {
+            return self.call(&body, depth);
+        }
+        if let Some(op) = self.words.native(name) {
+            return op.call(self.stack);
+        }
+
+        match name {
+            "words" => {
+                let builtins = MACHINE_WORDS.into_iter().chain(rpn::Op::ALL.iter().map(|op| op.name()));
+                let names: Vec<&str> = self.words.names().into_iter()
+                    .chain(self.words.native_names())
+                    .chain(builtins)
+                    .collect();
+                writeln!(self.out, "{}", names.join(" ")).map_err(rpn::Error::IO)
+            }
+            "apply" | "each" | "map" | "fold" => self.run_combinator(name, depth),
+            name => match rpn::Op::from_name(name) {
+                Some(op) => self.eval(op),
+                // Parsing checks every word, so this is a hand-built program.
+                None => Err(unknown_word(name)),
+            },
+        }
+    }
+
+    fn eval(&mut self, op: rpn::Op) -> rpn::Result<()> {
+        self.stack.eval(op)?;
+        if let Some(observer) = &mut self.observer {
+            observer.after_op(op, self.stack);
+        }
+        Ok(())
+    }
+
+    /// Runs one of the words that take a quotation. These live apart from
+    /// `run_word` to keep its frame small, since recursive words nest it.
+    fn run_combinator(&mut self, name: &str, depth: usize) -> rpn::Result<()> {
+        match name {
+            "apply" => {
+                let quote = self.pop_quote("apply")?;
+                self.call(&quote, depth)
+            }
+            "each" => {
+                let quote = self.pop_quote("each")?;
+                for val in self.pop_list("each")? {
+                    self.stack.push(val)?;
+                    self.call(&quote, depth)?;
+                }
+                Ok(())
+            }
+            "map" => {
+                let quote = self.pop_quote("map")?;
+                let mut vals = vec![];
+                for val in self.pop_list("map")? {
+                    // The quotation must replace each element with exactly one
+                    // result, and leave the values under it alone.
+                    let base = self.stack.len();
+                    self.stack.push(val)?;
+                    self.call(&quote, depth)?;
+                    match self.stack.len() {
+                        len if len <= base => return Err(rpn::Error::Underflow("map")),
+                        len if len > base + 1 => return Err(rpn::Error::Domain("map")),
+                        _ => vals.push(self.stack.pop()?),
+                    }
+                }
+                self.stack.push(rpn::Elt::List(vals))
+            }
+            "fold" => {
+                let quote = self.pop_quote("fold")?;
+                let init = self.stack.pop().map_err(|_| rpn::Error::Underflow("fold"))?;
+                let vals = self.pop_list("fold")?;
+                self.stack.push(init)?;
+                for val in vals {
+                    self.stack.push(val)?;
+                    self.call(&quote, depth)?;
+                }
+                Ok(())
+            }
+            _ => unreachable!("`{}` isn't a combinator", name),
+        }
+    }
+
+    /// Runs the body of a word or quotation, one call deeper.
+    fn call(&mut self, body: &[Node], depth: usize) -> rpn::Result<()> {
+        if depth >= MAX_CALL_DEPTH {
+            return Err(rpn::Error::Recursion);
+        }
+        self.run(body, depth + 1)
+    }
+
+    fn pop_quote(&mut self, name: &'static str) -> rpn::Result<Rc<Vec<Node>>> {
+        match self.stack.pop().map_err(|_| rpn::Error::Underflow(name))? {
+            rpn::Elt::Quote(quote) => Ok(quote),
+            _ => Err(rpn::Error::Type),
+        }
+    }
+
+    fn pop_list(&mut self, name: &'static str) -> rpn::Result<Vec<rpn::Elt>> {
+        match self.stack.pop().map_err(|_| rpn::Error::Underflow(name))? {
+            rpn::Elt::List(vals) => Ok(vals),
+            _ => Err(rpn::Error::Type),
+        }
+    }
+
+    fn pop_bool(&mut self) -> rpn::Result<bool> {
+        match self.stack.pop()? {
+            rpn::Elt::Bool(b) => Ok(b),
+            _ => Err(rpn::Error::Type),
+        }
+    }
+
+    fn pop_int(&mut self) -> rpn::Result<i32> {
+        match self.stack.pop()? {
+            rpn::Elt::Int(x) => Ok(x),
+            _ => Err(rpn::Error::Type),
+        }
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod tests {
-    use crate::rpn::{Stack, Error, Elt};
-    use crate::parser::evaluate_line;
+    use crate::ratio::Rational;
+    use crate::rpn::{Stack, Error, Elt, SyntaxError};
+    use crate::history::Snapshot;
+    use crate::compiler;
+    use crate::parser::{evaluate_line, evaluate_script, evaluate_transaction, load, parse, run_compiled, run_parsed, save};
+    use crate::words::Dictionary;
 
     #[test]
     fn test_evaluate_line_bool() {
         let mut stack = Stack::new();
+        let mut words = Dictionary::new();
         let s = "true".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Bool(true));
         let s = "false".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Bool(false));
     }
 
     #[test]
     fn test_evaluate_line_int() {
         let mut stack = Stack::new();
+        let mut words = Dictionary::new();
         let s = "12".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Int(12));
     }
 
     #[test]
     fn test_evaluate_line_plus() {
         let mut stack = Stack::new();
+        let mut words = Dictionary::new();
         let s = "12".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         let s = "13".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         let s = "+".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Int(25));
     }
 
     #[test]
     fn test_evaluate_line_neg() {
         let mut stack = Stack::new();
+        let mut words = Dictionary::new();
         let s = "false".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         let s = "~".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Bool(true));
     }
 
     #[test]
     fn test_evaluate_line_swap() {
         let mut stack = Stack::new();
+        let mut words = Dictionary::new();
         let s = "false".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         let s = "15".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         let s = "<->".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Bool(false));
         assert_eq!(stack.pop().unwrap(), Elt::Int(15));
     }
@@ -111,22 +1085,24 @@ mod tests {
     #[test]
     fn test_evaluate_line_eq() {
         let mut stack = Stack::new();
+        let mut words = Dictionary::new();
         let s = "12".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         let s = "15".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         let s = "=".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         assert_eq!(stack.pop().unwrap(), Elt::Bool(false));
     }
 
     #[test]
     fn test_evaluate_line_rand() {
         let mut stack = Stack::new();
+        let mut words = Dictionary::new();
         let s = "12".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         let s = "#".to_string();
-        assert!(evaluate_line(&mut stack, &s).is_ok());
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
         let res = stack.pop();
         assert!(res.is_ok());
         let res = res.unwrap();
@@ -134,11 +1110,588 @@ mod tests {
         assert!(res < Elt::Int(12));
     }
 
+    #[test]
+    fn test_evaluate_line_arith() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "7 2 - 3 * 4 / 5 % 2 ^".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(9));
+    }
+
+    #[test]
+    fn test_evaluate_line_negative_literal() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "-3 -".to_string();
+        let res = evaluate_line(&mut stack, &mut words, &s);
+        assert!(res.is_err());
+        if let Err(Error::Underflow("-")) = res {
+        } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_cmp() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "1 2 < 1 2 > 2 2 <= 1 2 >=".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Bool(false));
+        assert_eq!(stack.pop().unwrap(), Elt::Bool(true));
+        assert_eq!(stack.pop().unwrap(), Elt::Bool(false));
+        assert_eq!(stack.pop().unwrap(), Elt::Bool(true));
+    }
+
+    #[test]
+    fn test_evaluate_line_div_zero() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "1 0 /".to_string();
+        let res = evaluate_line(&mut stack, &mut words, &s);
+        assert!(res.is_err());
+        if let Err(Error::DivideByZero) = res {
+        } else { assert!(false); }
+    }
+
+    #[test]
+    #[cfg(not(feature = "bigint"))]
+    fn test_evaluate_line_overflow() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "2147483647 1 +".to_string();
+        let res = evaluate_line(&mut stack, &mut words, &s);
+        assert!(res.is_err());
+        if let Err(Error::Overflow) = res {
+        } else { assert!(false); }
+
+        let s = "2147483648".to_string();
+        let res = evaluate_line(&mut stack, &mut words, &s);
+        assert!(res.is_err());
+        if let Err(Error::Syntax(_)) = res {
+        } else { assert!(false); }
+    }
+
+    #[test]
+    #[cfg(feature = "bigint")]
+    fn test_evaluate_line_bigint() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "2147483647 1 + 2147483648 = 10 20 ^ 100000000000000000000 =".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Bool(true));
+        assert_eq!(stack.pop().unwrap(), Elt::Bool(true));
+
+        let s = "2147483648 1 -".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(i32::MAX));
+    }
+
+    #[test]
+    fn test_evaluate_line_float() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "3.5 1e1 *".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Float(35.0));
+    }
+
+    #[test]
+    fn test_evaluate_line_rational() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "1/3 1/6 + 2/-4 =".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Bool(false));
+
+        let s = "1/3 1/6 +".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Rational(Rational::new(1, 2).unwrap()));
+
+        let s = "1/0".to_string();
+        let res = evaluate_line(&mut stack, &mut words, &s);
+        assert!(res.is_err());
+        if let Err(Error::Syntax(_)) = res {
+        } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_nan_word() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "nan".to_string();
+        let res = evaluate_line(&mut stack, &mut words, &s);
+        assert!(res.is_err());
+        if let Err(Error::Syntax(_)) = res {
+        } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_float_words() {
+        // Floats that don't print with digits read back as they print.
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        assert!(evaluate_line(&mut stack, &mut words, "1e308 10.0 * dup ~ inf inf -").is_ok());
+        let printed: Vec<String> = stack.as_slice().iter().map(|x| x.to_string()).collect();
+        assert_eq!(printed, ["inf", "-inf", "NaN"]);
+
+        let mut again = Stack::new();
+        assert!(evaluate_line(&mut again, &mut words, &printed.join(" ")).is_ok());
+        assert_eq!(again.as_slice(), stack.as_slice());
+    }
+
+    #[test]
+    fn test_evaluate_line_stack_words() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "1 2 3 rot over tuck 4 pick 1 roll nip dup * drop depth".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(4));
+
+        let s = "clear 1 over".to_string();
+        let res = evaluate_line(&mut stack, &mut words, &s);
+        assert!(res.is_err());
+        if let Err(Error::Underflow("over")) = res {
+        } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_define() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = ": square dup * ; : cube dup square * ; 3 cube".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(27));
+        assert_eq!(words.names(), vec!["cube", "square"]);
+    }
+
+    #[test]
+    fn test_evaluate_line_redefine() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = ": inc 1 + ; : twice inc inc ; : inc 10 + ; 0 twice".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(20));
+
+        let s = ": + - ; 5 3 +".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(2));
+    }
+
+    #[test]
+    fn test_evaluate_line_recursion() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = ": forever forever ; forever".to_string();
+        let res = evaluate_line(&mut stack, &mut words, &s);
+        assert!(res.is_err());
+        if let Err(Error::Recursion) = res {
+        } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_bad_define() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        for s in [": square dup *", ":", ": 12 dup ;", ": a : b ; ;"] {
+            let res = evaluate_line(&mut stack, &mut words, s);
+            assert!(res.is_err());
+            if let Err(Error::Syntax(_)) = res {
+            } else { assert!(false); }
+        }
+        assert!(words.names().is_empty());
+    }
+
+    #[test]
+    fn test_evaluate_line_words() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = ": square dup * ; words".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn test_evaluate_line_if() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "1 2 < if 10 else 20 then 1 2 > if 30 then".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(10));
+        assert!(stack.is_empty());
+
+        let s = "1 if 2 then".to_string();
+        let res = evaluate_line(&mut stack, &mut words, &s);
+        assert!(res.is_err());
+        if let Err(Error::Type) = res {
+        } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_until() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "1 begin 2 * dup 100 > until".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(128));
+    }
+
+    #[test]
+    fn test_evaluate_line_do_loop() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "0 5 0 do i + loop".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(10));
+
+        let s = "0 3 1 do 3 1 do i j * + loop loop".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(9));
+    }
+
+    #[test]
+    fn test_evaluate_line_loop_index() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = ": sum 0 <-> 0 do i + loop ; 4 sum".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(6));
+
+        let s = "1 0 do j loop".to_string();
+        let res = evaluate_line(&mut stack, &mut words, &s);
+        assert!(res.is_err());
+        if let Err(Error::Underflow("j")) = res {
+        } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_recursive_word() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = ": fact dup 1 > if dup 1 - fact * then ; 10 fact".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(3628800));
+
+        let s = ": down dup 0 > if 1 - begin down true until then ; 250 down".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(0));
+    }
+
+    #[test]
+    fn test_evaluate_line_unbalanced() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let lines = [
+            "1 true if 2", "1 then", "true if 1 else 2 else 3 then", "begin 1",
+            "1 until", "5 0 do", "loop", "true if : a ; then", ";",
+        ];
+        for s in lines {
+            let res = evaluate_line(&mut stack, &mut words, s);
+            assert!(res.is_err());
+            if let Err(Error::Syntax(_)) = res {
+            } else { assert!(false); }
+            // Nothing on a line that doesn't parse runs.
+            assert!(stack.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_evaluate_line_deep_nesting() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let deep = 50000;
+        let lines = [
+            format!("{}{}", "[ ".repeat(deep), "] ".repeat(deep)),
+            format!("{}{}", "{ ".repeat(deep), "} ".repeat(deep)),
+            format!("{}1{}", "true if ".repeat(deep), " then".repeat(deep)),
+            format!("{}{}", "begin ".repeat(deep), "true until ".repeat(deep)),
+            format!("{{ {}", "[ ".repeat(deep)),
+        ];
+        for s in lines {
+            let res = evaluate_line(&mut stack, &mut words, &s);
+            assert!(matches!(res, Err(Error::Syntax(_))));
+            assert!(stack.is_empty());
+        }
+
+        let nesting = crate::parser::MAX_NESTING;
+        let s = format!("{}{}", "[ ".repeat(nesting), "] ".repeat(nesting));
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.len(), 1);
+    }
+
+    #[test]
+    fn test_evaluate_script() {
+        let s = ": square\n  dup *\n;\n3 square\n4 square\nquit\n5";
+        let stack = evaluate_script(Stack::new(), s, None).unwrap();
+        assert_eq!(stack.as_slice(), &[Elt::Int(9), Elt::Int(16)]);
+
+        let res = evaluate_script(Stack::new(), "1 +", None);
+        assert!(res.is_err());
+        if let Err(Error::Underflow("+")) = res {
+        } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_transaction() {
+        let mut session = Snapshot { stack: Stack::new(), words: Dictionary::new() };
+        assert!(evaluate_transaction(&mut session, "1 2", None).is_ok());
+
+        let res = evaluate_transaction(&mut session, ": three 3 ; drop drop drop", None);
+        assert!(res.is_err());
+        if let Err(Error::Underflow("drop")) = res {
+        } else { assert!(false); }
+        assert_eq!(session.stack.as_slice(), &[Elt::Int(1), Elt::Int(2)]);
+        assert!(session.words.names().is_empty());
+
+        let before = evaluate_transaction(&mut session, "+", None).unwrap();
+        assert_eq!(before.stack.as_slice(), &[Elt::Int(1), Elt::Int(2)]);
+        assert_eq!(session.stack.pop().unwrap(), Elt::Int(3));
+    }
+
+    fn syntax_error(s: &str) -> SyntaxError {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        match evaluate_line(&mut stack, &mut words, s) {
+            Err(Error::Syntax(err)) => *err,
+            res => panic!("expected a syntax error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_syntax_error_unknown() {
+        let err = syntax_error("1 2 ~false");
+        assert_eq!(err.token, "~false");
+        assert_eq!(err.span, 4..10);
+        assert_eq!(err.suggestion.as_deref(), Some("did you mean `~ false`?"));
+        assert_eq!(err.render("1 2 ~false"), "1 2 ~false\n    ^^^^^^ unknown word `~false`; did you mean `~ false`?");
+
+        let err = syntax_error("3 dpu *");
+        assert_eq!(err.span, 2..5);
+        assert_eq!(err.suggestion.as_deref(), Some("did you mean `dup`?"));
+
+        let err = syntax_error("xyzzy");
+        assert_eq!(err.suggestion, None);
+    }
+
+    #[test]
+    fn test_syntax_error_control() {
+        let err = syntax_error("1 if 2");
+        assert_eq!(err.token, "if");
+        assert_eq!(err.span, 2..4);
+        assert_eq!(err.suggestion.as_deref(), Some("add `then`"));
+
+        let err = syntax_error("true if 1 until");
+        assert_eq!(err.token, "until");
+        assert_eq!(err.suggestion.as_deref(), Some("did you mean `then`?"));
+
+        let err = syntax_error("1 then");
+        assert_eq!(err.message, "`then` without a matching `if`");
+
+        let err = syntax_error("1 :");
+        assert_eq!(err.token, "");
+        assert_eq!(err.span, 3..3);
+    }
+
+    #[test]
+    fn test_syntax_error_render_script() {
+        let src = "1 2 +\n3 then\n";
+        let err = match evaluate_script(Stack::new(), src, None) {
+            Err(Error::Syntax(err)) => err,
+            _ => panic!("expected a syntax error"),
+        };
+        assert_eq!(err.render(src), "line 2:\n3 then\n  ^^^^ `then` without a matching `if`");
+    }
+
+    #[test]
+    fn test_evaluate_line_seed() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "5 seed 1000 # 1000 # 5 seed 1000 # 1000 #".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        let vals = stack.as_slice();
+        assert_eq!(vals[0..2], vals[2..4]);
+
+        let s = "0 #".to_string();
+        let res = evaluate_line(&mut stack, &mut words, &s);
+        assert!(res.is_err());
+        if let Err(Error::Domain("#")) = res {
+        } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_evaluate_line_strings() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = r#""say \"hi\"" "" concat len "a b"[dup]"#.to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert!(matches!(stack.pop().unwrap(), Elt::Quote(_)));
+        assert_eq!(stack.pop().unwrap(), Elt::Str("a b".to_string()));
+        assert_eq!(stack.pop().unwrap(), Elt::Int(8));
+
+        let err = syntax_error("1 \"abc");
+        assert_eq!(err.span, 2..3);
+        let err = syntax_error(r#""\q""#);
+        assert_eq!(err.token, r#""\q""#);
+    }
+
+    #[test]
+    fn test_evaluate_line_lists() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "{ 1 2 3 } [ dup * ] map { 4 } concat".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        let list = Elt::List(vec![Elt::Int(1), Elt::Int(4), Elt::Int(9), Elt::Int(4)]);
+        assert_eq!(stack.pop().unwrap(), list);
+
+        let s = "{ 1 2 3 4 } 0 [ + ] fold { 1 { true \"x\" } [ 2 ] } len".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(3));
+        assert_eq!(stack.pop().unwrap(), Elt::Int(10));
+
+        let s = "{ 1 2 } [ 10 * ] each 3 [ 1 + ] apply".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.as_slice(), &[Elt::Int(10), Elt::Int(20), Elt::Int(4)]);
+
+        let err = syntax_error("{ 1 dup }");
+        assert_eq!(err.token, "dup");
+        let err = syntax_error("[ 1 }");
+        assert_eq!(err.suggestion.as_deref(), Some("did you mean `]`?"));
+        let mut stack = Stack::new();
+        assert!(matches!(
+            evaluate_line(&mut stack, &mut words, "{ 1 } [ drop ] map"),
+            Err(Error::Underflow("map"))
+        ));
+        // `map` doesn't take values from under the list, or leave extra ones.
+        assert!(matches!(
+            evaluate_line(&mut stack, &mut words, "5 { 1 } [ drop ] map"),
+            Err(Error::Underflow("map"))
+        ));
+        assert!(matches!(
+            evaluate_line(&mut stack, &mut words, "{ 1 2 } [ dup ] map"),
+            Err(Error::Domain("map"))
+        ));
+        assert!(matches!(evaluate_line(&mut stack, &mut words, "1 apply"), Err(Error::Type)));
+    }
+
+    #[test]
+    fn test_evaluate_line_quote_recursion() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "[ dup apply ] dup apply".to_string();
+        assert!(matches!(
+            evaluate_line(&mut stack, &mut words, &s),
+            Err(Error::Recursion)
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_line_vars() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "3 !x @x @x * !x @x 1 !dup".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(9));
+        assert_eq!(words.fetch("dup"), Some(&Elt::Int(1)));
+
+        let res = evaluate_line(&mut stack, &mut words, "@y");
+        assert!(res.is_err());
+        if let Err(Error::Unset(name)) = res {
+            assert_eq!(name, "y");
+        } else { assert!(false); }
+        assert!(matches!(evaluate_line(&mut stack, &mut words, "!x"), Err(Error::Underflow("!"))));
+        assert_eq!(syntax_error(": !x 1 ;").token, "!x");
+    }
+
+    #[test]
+    fn test_evaluate_line_infix() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "2 !x infix (x + 1) * -x ^ 2\n1 +".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.pop().unwrap(), Elt::Int(-11));
+
+        let err = syntax_error("1\ninfix 2 +\n");
+        assert_eq!(err.span, 11..11);
+        assert_eq!(syntax_error(": infix 1 ;").token, "infix");
+
+        // An expression also ends at a word that closes a block.
+        let s = "5 !x : f infix x * 2 ; f [ infix x - 1 ] apply true if infix 1 else 0 then".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        assert_eq!(stack.as_slice(), &[Elt::Int(10), Elt::Int(4), Elt::Int(1)]);
+        assert_eq!(syntax_error("[ infix 1 + ]").span, 11..11);
+    }
+
+    #[test]
+    fn test_compiled_matches_parsed() {
+        let programs = [
+            "2 3 + 4 * 1 0 /",
+            "1 <-> <->",
+            "5 dup drop 2 over drop -",
+            ": + * ; 2 3 +",
+            "0 10 0 do i 2 % 0 = if i + then loop \"x\" len +",
+            "{ 1 2 3 } [ dup * ] map",
+        ];
+        for src in programs {
+            let words = Dictionary::new();
+            let program = parse(src, &words).unwrap();
+            let code = compiler::compile(&program, &words);
+
+            // Each run defines the program's words in a dictionary of its own.
+            let mut parsed = Stack::new();
+            let res = run_parsed(&mut parsed, &mut Dictionary::new(), &program).map_err(|err| format!("{:?}", err));
+            let mut compiled = Stack::new();
+            let res2 = run_compiled(&mut compiled, &mut Dictionary::new(), &code).map_err(|err| format!("{:?}", err));
+            assert_eq!(res, res2, "{}", src);
+            // What a failed line leaves is rolled back, so it may differ.
+            if res.is_ok() {
+                assert_eq!(parsed.as_slice(), compiled.as_slice(), "{}", src);
+            }
+        }
+    }
+
+    #[test]
+    fn test_evaluate_line_units() {
+        let mut stack = Stack::new();
+        let mut words = Dictionary::new();
+        let s = "36km/h 1h * 10 to min 2 *  { 1.5MiB } [ to KiB ] map".to_string();
+        assert!(evaluate_line(&mut stack, &mut words, &s).is_ok());
+        let vals: Vec<String> = stack.as_slice().iter().map(|val| val.to_string()).collect();
+        assert_eq!(vals, vec!["36km", "20min", "{ 1536KiB }"]);
+
+        assert!(matches!(evaluate_line(&mut stack, &mut words, "1m 1s +"), Err(Error::Type)));
+        assert!(matches!(evaluate_line(&mut stack, &mut words, "1m to kg"), Err(Error::Type)));
+        assert_eq!(syntax_error("1m to furlongs").token, "furlongs");
+        assert_eq!(syntax_error("5parsec").token, "5parsec");
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut session = Snapshot { stack: Stack::new(), words: Dictionary::new() };
+        let s = ": even ;
+                 : odd dup 0 = if drop false else 1 - even then ;
+                 : even dup 0 = if drop true else 1 - odd then ;
+                 { 1 \"two\" [ odd ] } !x inf dup - 1/2 @x 7 odd";
+        assert!(evaluate_transaction(&mut session, s, None).is_ok());
+
+        let path = std::env::temp_dir().join(format!("hw04-session-{}.rpn", std::process::id()));
+        let path = path.to_str().unwrap();
+        save(&session, path).unwrap();
+        let loaded = load(&Snapshot { stack: Stack::new(), words: Dictionary::new() }, path);
+        std::fs::remove_file(path).unwrap();
+
+        let mut loaded = loaded.unwrap();
+        assert_eq!(loaded.stack.as_slice(), session.stack.as_slice());
+        assert_eq!(loaded.words.fetch("x"), session.words.fetch("x"));
+        assert!(evaluate_line(&mut loaded.stack, &mut loaded.words, "4 even").is_ok());
+        assert_eq!(loaded.stack.pop().unwrap(), Elt::Bool(true));
+        assert_eq!(loaded.to_string(), session.to_string());
+    }
+
     #[test]
     fn test_evaluate_line_quit() {
         let mut stack = Stack::new();
+        let mut words = Dictionary::new();
         let s = "quit".to_string();
-        let res = evaluate_line(&mut stack, &s);
+        let res = evaluate_line(&mut stack, &mut words, &s);
         assert!(res.is_err());
         if let Err(Error::Quit) = res {
         } else { assert!(false); }
@@ -147,10 +1700,11 @@ mod tests {
     #[test]
     fn test_evaluate_line_bad_parse() {
         let mut stack = Stack::new();
+        let mut words = Dictionary::new();
         let s = "~false".to_string();
-        let res = evaluate_line(&mut stack, &s);
+        let res = evaluate_line(&mut stack, &mut words, &s);
         assert!(res.is_err());
-        if let Err(Error::Syntax) = res {
+        if let Err(Error::Syntax(_)) = res {
         } else { assert!(false); }
     }
 }
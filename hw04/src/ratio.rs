@@ -0,0 +1,196 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+/// An exact fraction `num / den`.
+///
+/// A `Rational` is always kept in lowest terms with a positive denominator, so
+/// two equal fractions have equal fields. Operations are checked, and return
+/// `None` when the reduced result doesn't fit in an `i64` fraction.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Rational {
+    num: i64,
+    den: i64,
+}
+
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a.abs()
+}
+
+impl Rational {
+    /// Creates the fraction `num / den` in lowest terms, or `None` if `den`
+    /// is zero.
+    pub fn new(num: i64, den: i64) -> Option<Rational> {
+        Rational::reduce(num as i128, den as i128)
+    }
+
+    /// Reduces a fraction computed with wider intermediates.
+    fn reduce(num: i128, den: i128) -> Option<Rational> {
+        if den == 0 {
+            return None;
+        }
+        let g = gcd(num, den);
+        let (mut num, mut den) = (num / g, den / g);
+        if den < 0 {
+            num = -num;
+            den = -den;
+        }
+        Some(Rational {
+            num: i64::try_from(num).ok()?,
+            den: i64::try_from(den).ok()?,
+        })
+    }
+
+    /// The numerator, which carries the sign.
+    pub fn numer(&self) -> i64 {
+        self.num
+    }
+
+    /// The denominator, which is always positive.
+    pub fn denom(&self) -> i64 {
+        self.den
+    }
+
+    /// Whether this fraction is a whole number.
+    pub fn is_integer(&self) -> bool {
+        self.den == 1
+    }
+
+    /// The nearest `f64` to this fraction.
+    pub fn to_f64(self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    pub fn checked_add(self, other: Rational) -> Option<Rational> {
+        let (a, b, c, d) = self.wide(other);
+        Rational::reduce(a * d + c * b, b * d)
+    }
+
+    pub fn checked_sub(self, other: Rational) -> Option<Rational> {
+        let (a, b, c, d) = self.wide(other);
+        Rational::reduce(a * d - c * b, b * d)
+    }
+
+    pub fn checked_mul(self, other: Rational) -> Option<Rational> {
+        let (a, b, c, d) = self.wide(other);
+        Rational::reduce(a * c, b * d)
+    }
+
+    /// Divides by `other`, or returns `None` if it is zero.
+    pub fn checked_div(self, other: Rational) -> Option<Rational> {
+        let (a, b, c, d) = self.wide(other);
+        Rational::reduce(a * d, b * c)
+    }
+
+    /// The remainder of truncated division, `self - other * trunc(self / other)`,
+    /// which has the sign of `self` like `%` on integers.
+    pub fn checked_rem(self, other: Rational) -> Option<Rational> {
+        let (a, b, c, d) = self.wide(other);
+        if c == 0 {
+            return None;
+        }
+        // self / other = (a * d) / (b * c), so the remainder is
+        // ((a * d) % (b * c)) / (b * d).
+        Rational::reduce((a * d) % (b * c), b * d)
+    }
+
+    /// Raises to an integer power. A negative power of zero returns `None`.
+    pub fn checked_pow(self, exp: i32) -> Option<Rational> {
+        let base = if exp < 0 {
+            Rational::new(1, 1)?.checked_div(self)?
+        } else {
+            self
+        };
+        // Exponentiation by squaring.
+        let (mut res, mut base, mut exp) = (Rational::from(1), base, exp.unsigned_abs());
+        while exp > 0 {
+            if exp & 1 == 1 {
+                res = res.checked_mul(base)?;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.checked_mul(base)?;
+            }
+        }
+        Some(res)
+    }
+
+    pub fn checked_neg(self) -> Option<Rational> {
+        Rational::reduce(-(self.num as i128), self.den as i128)
+    }
+
+    fn wide(self, other: Rational) -> (i128, i128, i128, i128) {
+        (self.num as i128, self.den as i128, other.num as i128, other.den as i128)
+    }
+}
+
+impl From<i32> for Rational {
+    fn from(x: i32) -> Rational {
+        Rational { num: x as i64, den: 1 }
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Rational) -> Ordering {
+        let (a, b, c, d) = self.wide(*other);
+        (a * d).cmp(&(c * b))
+    }
+}
+
+impl fmt::Debug for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn r(num: i64, den: i64) -> Rational {
+        Rational::new(num, den).unwrap()
+    }
+
+    #[test]
+    fn test_new_reduces() {
+        assert_eq!(r(2, 4), r(1, 2));
+        assert_eq!(r(3, -6), r(-1, 2));
+        assert_eq!(r(-1, 2).denom(), 2);
+        assert!(Rational::new(1, 0).is_none());
+    }
+
+    #[test]
+    fn test_arith() {
+        assert_eq!(r(1, 2).checked_add(r(1, 3)), Some(r(5, 6)));
+        assert_eq!(r(1, 2).checked_sub(r(1, 3)), Some(r(1, 6)));
+        assert_eq!(r(2, 3).checked_mul(r(3, 4)), Some(r(1, 2)));
+        assert_eq!(r(1, 2).checked_div(r(1, 4)), Some(r(2, 1)));
+        assert_eq!(r(1, 2).checked_div(r(0, 1)), None);
+        assert_eq!(r(7, 2).checked_rem(r(1, 1)), Some(r(1, 2)));
+        assert_eq!(r(-7, 2).checked_rem(r(1, 1)), Some(r(-1, 2)));
+        assert_eq!(r(2, 3).checked_pow(-2), Some(r(9, 4)));
+        assert_eq!(r(0, 1).checked_pow(-1), None);
+    }
+
+    #[test]
+    fn test_overflow() {
+        assert_eq!(r(i64::MAX, 1).checked_add(r(1, 1)), None);
+        assert_eq!(r(i64::MIN, 1).checked_neg(), None);
+    }
+
+    #[test]
+    fn test_cmp() {
+        assert!(r(1, 3) < r(1, 2));
+        assert!(r(-1, 2) < r(-1, 3));
+    }
+}
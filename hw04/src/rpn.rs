@@ -1,35 +1,516 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
 use std::io;
+use std::ops::Range;
+use std::rc::Rc;
 use rand;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+#[cfg(feature = "bigint")]
+use num_bigint::BigInt;
+#[cfg(feature = "bigint")]
+use num_traits::ToPrimitive;
 
-#[derive(Eq, PartialEq, Ord, PartialOrd, Debug)]
-/// An element of the stack. May be either integer or boolean.
+use crate::ast::{Block, Node};
+use crate::ratio::Rational;
+use crate::units::{Quantity, Unit};
+
+#[derive(Clone, Debug)]
+/// An element of the stack. May be a boolean, a number, a quantity with a
+/// unit, a string, a list of elements or a quotation of code.
+///
+/// Arithmetic promotes mixed operands to the wider of the two number types,
+/// `Int` < `Big` < `Rational` < `Float`. `Int` arithmetic stays integral, so
+/// `7 2 /` truncates to `3`; write the fraction `7/2` or use a `Float` operand
+/// for a fractional result. Results are demoted again when they fit:
+/// a `Rational` with denominator 1 becomes an `Int`, and with the `bigint`
+/// feature a `Big` never holds a value that fits in an `i32`.
+///
+/// Equality and ordering compare numbers by their exact value across types
+/// (`1 = 1.0`, but `1/3 0.3333333333333333 <`, since the float is a little
+/// less than a third).
+/// To keep the order total, `NaN` equals itself and is greater than every
+/// other number. Every boolean sorts before every number, and then come
+/// quantities, strings, lists and quotations. Quantities compare by
+/// dimension, then by value, strings and lists element by element, and
+/// quotations by their text.
 pub enum Elt {
     Int(i32),
     Bool(bool),
+    /// Integers that overflow an `i32` are promoted to `Big` instead of
+    /// failing with `Error::Overflow`.
+    #[cfg(feature = "bigint")]
+    Big(BigInt),
+    Rational(Rational),
+    Float(f64),
+    /// A number with a unit, like `5m`. Adding or comparing quantities of
+    /// different dimensions fails with `Error::Type`.
+    Quantity(Quantity),
+    Str(String),
+    List(Vec<Elt>),
+    /// A block of code, written `[ ... ]`, which runs when it is applied.
+    Quote(Rc<Vec<Node>>),
+}
+
+/// Two numeric operands, promoted to a common type.
+enum Pair {
+    Int(i32, i32),
+    #[cfg(feature = "bigint")]
+    Big(BigInt, BigInt),
+    Rational(Rational, Rational),
+    Float(f64, f64),
+}
+
+impl Elt {
+    /// Wraps a fraction, demoting it to an `Int` if it is a whole number.
+    pub fn from_rational(x: Rational) -> Elt {
+        if x.is_integer() {
+            if let Ok(n) = i32::try_from(x.numer()) {
+                return Elt::Int(n);
+            }
+        }
+        Elt::Rational(x)
+    }
+
+    /// The name of this element's type.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Elt::Int(_) => "Int",
+            Elt::Bool(_) => "Bool",
+            #[cfg(feature = "bigint")]
+            Elt::Big(_) => "Big",
+            Elt::Rational(_) => "Rational",
+            Elt::Float(_) => "Float",
+            Elt::Quantity(_) => "Quantity",
+            Elt::Str(_) => "Str",
+            Elt::List(_) => "List",
+            Elt::Quote(_) => "Quote",
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Elt::Quantity(x) => x.base_value() == 0.0,
+            x => x.is_number() && x.to_f64().is_ok_and(|x| x == 0.0),
+        }
+    }
+
+    fn is_number(&self) -> bool {
+        match self {
+            Elt::Int(_) | Elt::Rational(_) | Elt::Float(_) => true,
+            #[cfg(feature = "bigint")]
+            Elt::Big(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Orders the kinds of element, for comparing elements of different types.
+    fn rank(&self) -> u8 {
+        match self {
+            Elt::Bool(_) => 0,
+            Elt::Quantity(_) => 2,
+            Elt::Str(_) => 3,
+            Elt::List(_) => 4,
+            Elt::Quote(_) => 5,
+            _ => 1,
+        }
+    }
+
+    fn to_f64(&self) -> Result<f64> {
+        match self {
+            Elt::Int(x) => Ok(*x as f64),
+            #[cfg(feature = "bigint")]
+            Elt::Big(x) => Ok(x.to_f64().unwrap_or(f64::NAN)),
+            Elt::Rational(x) => Ok(x.to_f64()),
+            Elt::Float(x) => Ok(*x),
+            _ => Err(Error::Type),
+        }
+    }
+
+    /// Shows a quantity in `unit`, or makes a plain number into a quantity of
+    /// `unit`. Fails with `Error::Type` if the dimensions differ.
+    pub fn convert(self, unit: &Unit) -> Result<Elt> {
+        match self {
+            Elt::Quantity(x) => x.to(unit).map(Elt::Quantity).ok_or(Error::Type),
+            x => Ok(Elt::Quantity(Quantity::new(x.to_f64()?, unit.clone()))),
+        }
+    }
+
+    fn to_rational(&self) -> Result<Rational> {
+        match self {
+            Elt::Int(x) => Ok(Rational::from(*x)),
+            #[cfg(feature = "bigint")]
+            Elt::Big(x) => {
+                let x = i64::try_from(x).map_err(|_| Error::Overflow)?;
+                Rational::new(x, 1).ok_or(Error::Overflow)
+            }
+            Elt::Rational(x) => Ok(*x),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+#[cfg(feature = "bigint")]
+impl Elt {
+    /// Wraps a big integer, demoting it to an `Int` if it fits.
+    pub fn from_big(x: BigInt) -> Elt {
+        match i32::try_from(&x) {
+            Ok(x) => Elt::Int(x),
+            Err(_) => Elt::Big(x),
+        }
+    }
+
+    /// Widens an integer element to a big integer.
+    fn to_big(&self) -> Result<BigInt> {
+        match self {
+            Elt::Int(x) => Ok(BigInt::from(*x)),
+            Elt::Big(x) => Ok(x.clone()),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl Pair {
+    /// Promotes `x` and `y` to the wider of their two number types.
+    fn promote(x: &Elt, y: &Elt) -> Result<Pair> {
+        match (x, y) {
+            (Elt::Int(x), Elt::Int(y)) => Ok(Pair::Int(*x, *y)),
+            (x, y) if !x.is_number() || !y.is_number() => Err(Error::Type),
+            (Elt::Float(_), _) | (_, Elt::Float(_)) => Ok(Pair::Float(x.to_f64()?, y.to_f64()?)),
+            (Elt::Rational(_), _) | (_, Elt::Rational(_)) => {
+                Ok(Pair::Rational(x.to_rational()?, y.to_rational()?))
+            }
+            #[cfg(feature = "bigint")]
+            (x, y) => Ok(Pair::Big(x.to_big()?, y.to_big()?)),
+            #[cfg(not(feature = "bigint"))]
+            (_, _) => unreachable!("unhandled number types"),
+        }
+    }
+
+    /// Compares the two operands.
+    fn cmp(&self) -> Ordering {
+        match self {
+            Pair::Int(x, y) => x.cmp(y),
+            #[cfg(feature = "bigint")]
+            Pair::Big(x, y) => x.cmp(y),
+            Pair::Rational(x, y) => x.cmp(y),
+            Pair::Float(x, y) => match (x.is_nan(), y.is_nan()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                // Neither is NaN, so they are ordered.
+                (false, false) => x.partial_cmp(y).unwrap(),
+            },
+        }
+    }
+
+    /// Applies one of the binary arithmetic operators to the operands.
+    fn arith(self, op: &Op) -> Result<Elt> {
+        match self {
+            Pair::Int(x, y) => match int_arith(op, x, y) {
+                #[cfg(feature = "bigint")]
+                Err(Error::Overflow) => {
+                    big_arith(op, &BigInt::from(x), &BigInt::from(y)).map(Elt::from_big)
+                }
+                res => res.map(Elt::Int),
+            },
+            #[cfg(feature = "bigint")]
+            Pair::Big(x, y) => big_arith(op, &x, &y).map(Elt::from_big),
+            Pair::Rational(x, y) => rational_arith(op, x, y),
+            Pair::Float(x, y) => float_arith(op, x, y).map(Elt::Float),
+        }
+    }
+}
+
+impl fmt::Display for Elt {
+    /// Writes the element as it would be written in a program.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Elt::Int(x) => write!(f, "{}", x),
+            Elt::Bool(x) => write!(f, "{}", x),
+            #[cfg(feature = "bigint")]
+            Elt::Big(x) => write!(f, "{}", x),
+            Elt::Rational(x) => write!(f, "{:?}", x),
+            // Debug keeps the point on whole floats, so they read back as floats.
+            Elt::Float(x) => write!(f, "{:?}", x),
+            Elt::Quantity(x) => write!(f, "{}", x),
+            Elt::Str(x) => {
+                write!(f, "\"")?;
+                for c in x.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Elt::List(xs) => {
+                write!(f, "{{ ")?;
+                for x in xs {
+                    write!(f, "{} ", x)?;
+                }
+                write!(f, "}}")
+            }
+            Elt::Quote(body) => write!(f, "[ {}]", Block(body)),
+        }
+    }
+}
+
+impl PartialEq for Elt {
+    fn eq(&self, other: &Elt) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Elt {}
+
+impl PartialOrd for Elt {
+    fn partial_cmp(&self, other: &Elt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
+impl Ord for Elt {
+    fn cmp(&self, other: &Elt) -> Ordering {
+        match (self, other) {
+            (Elt::Bool(x), Elt::Bool(y)) => x.cmp(y),
+            (Elt::Str(x), Elt::Str(y)) => x.cmp(y),
+            (Elt::List(x), Elt::List(y)) => x.cmp(y),
+            (Elt::Quote(x), Elt::Quote(y)) => {
+                Block(x).to_string().cmp(&Block(y).to_string())
+            }
+            (Elt::Quantity(x), Elt::Quantity(y)) => x.dimension().cmp(&y.dimension())
+                .then_with(|| Pair::Float(x.base_value(), y.base_value()).cmp()),
+            (x, y) if x.is_number() && y.is_number() => cmp_numbers(x, y),
+            (x, y) => x.rank().cmp(&y.rank()),
+        }
+    }
+}
+
+/// Compares two numbers of any types by their exact values, so that the order
+/// stays transitive. Promoting to a float would round, and make `1/3` equal to
+/// the float nearest it.
+fn cmp_numbers(x: &Elt, y: &Elt) -> Ordering {
+    match (x, y) {
+        (Elt::Float(x), Elt::Float(y)) => Pair::Float(*x, *y).cmp(),
+        (Elt::Float(x), y) => cmp_float(*x, y),
+        (x, Elt::Float(y)) => cmp_float(*y, x).reverse(),
+        // The `Big` may not fit in a fraction, so compare `b` with `n/d` as
+        // `b·d` with `n`.
+        #[cfg(feature = "bigint")]
+        (Elt::Big(b), Elt::Rational(r)) => (b * r.denom()).cmp(&BigInt::from(r.numer())),
+        #[cfg(feature = "bigint")]
+        (Elt::Rational(r), Elt::Big(b)) => BigInt::from(r.numer()).cmp(&(b * r.denom())),
+        (x, y) => Pair::promote(x, y).expect("exact numbers promote").cmp(),
+    }
+}
+
+/// Compares the float `x` exactly with `y`, which is an `Int`, `Big` or
+/// `Rational`. NaN is greater than every number, as in `Pair::cmp`.
+fn cmp_float(x: f64, y: &Elt) -> Ordering {
+    if x.is_nan() {
+        return Ordering::Greater;
+    }
+    if x.is_infinite() {
+        return if x > 0.0 { Ordering::Greater } else { Ordering::Less };
+    }
+    let (m, e) = decompose(x);
+    match y {
+        Elt::Int(n) => cmp_shifted(m as i128, *n as i128, e),
+        // `m·2^e` against `n/d`, with `d` positive, is `m·d·2^e` against `n`.
+        Elt::Rational(r) => cmp_shifted(m as i128 * r.denom() as i128, r.numer() as i128, e),
+        #[cfg(feature = "bigint")]
+        Elt::Big(n) => {
+            let m = BigInt::from(m);
+            if e >= 0 {
+                (m << e as usize).cmp(n)
+            } else {
+                m.cmp(&(n << (-e) as usize))
+            }
+        }
+        _ => unreachable!("not an exact number"),
+    }
+}
+
+/// Splits a finite float into `m` and `e` such that it is exactly `m·2^e`.
+fn decompose(x: f64) -> (i64, i32) {
+    let bits = x.to_bits();
+    let sign = if bits >> 63 == 0 { 1 } else { -1 };
+    let exponent = ((bits >> 52) & 0x7ff) as i32;
+    let fraction = (bits & ((1 << 52) - 1)) as i64;
+    if exponent == 0 {
+        // Subnormal, without the implicit leading bit.
+        (sign * fraction, -1074)
+    } else {
+        (sign * (fraction | 1 << 52), exponent - 1075)
+    }
+}
+
+/// Compares `a·2^e` with `b`, where `a` and `b` are both less than `2^117` in
+/// magnitude.
+fn cmp_shifted(a: i128, b: i128, e: i32) -> Ordering {
+    if e >= 0 {
+        match shift_left(a, e.unsigned_abs()) {
+            Some(a) => a.cmp(&b),
+            // `a·2^e` is too big for `b` to matter.
+            None => a.cmp(&0),
+        }
+    } else {
+        match shift_left(b, e.unsigned_abs()) {
+            Some(b) => a.cmp(&b),
+            None => 0.cmp(&b),
+        }
+    }
+}
+
+/// `x·2^k`, or `None` if that is at least `2^126` in magnitude.
+fn shift_left(x: i128, k: u32) -> Option<i128> {
+    if x == 0 {
+        Some(0)
+    } else if x.unsigned_abs().leading_zeros() > k + 1 {
+        Some(x << k)
+    } else {
+        None
+    }
+}
 
 #[derive(Debug)]
 /// An RPN calculator error.
 pub enum Error {
-    /// Tried to pop from an empty stack.
-    Underflow,
+    /// Tried to pop from an empty stack. Names the word that needed more
+    /// values, or `pop` for a bare `Stack::pop`.
+    Underflow(&'static str),
     /// Tried to operate on invalid types (e.g. 4 + true)
     Type,
     /// Unable to parse the input.
-    Syntax,
+    Syntax(Box<SyntaxError>),
+    /// Tried to divide (or take a modulus) by zero.
+    DivideByZero,
+    /// The result of an integer operation doesn't fit in an `i32`.
+    Overflow,
+    /// User-defined words called each other too deeply.
+    Recursion,
+    /// An argument was of the right type but outside the values an operator
+    /// accepts, like the bound in `0 #`. Names the operator.
+    Domain(&'static str),
+    /// Tried to fetch a variable with `@name` before storing to it. Names
+    /// the variable.
+    Unset(String),
+    /// A program went beyond a limit set by `Interpreter::set_limits`. Names
+    /// the limit, `steps` or `stack`.
+    Limit(&'static str),
+    /// A native word defined by an embedding program failed, and explains
+    /// why.
+    Native(String),
+    /// The user stopped the program in the debugger.
+    Stopped,
     /// Some IO error occurred.
     IO(io::Error),
     /// The user quit the program (with `quit`).
     Quit,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
+/// Where and why some input failed to parse.
+pub struct SyntaxError {
+    /// The offending token, or `""` if a token is missing at the end.
+    pub token: String,
+    /// The byte offsets of the token in the input.
+    pub span: Range<usize>,
+    /// What's wrong with the token.
+    pub message: String,
+    /// A likely fix, such as "did you mean `~ false`?".
+    pub suggestion: Option<String>,
+}
+
+impl SyntaxError {
+    /// Shows the line of `src` that the error is in, with a caret under the
+    /// offending token and the message beside it.
+    pub fn render(&self, src: &str) -> String {
+        let start = self.span.start.min(src.len());
+        let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = src[start..].find('\n').map_or(src.len(), |i| start + i);
+        let line = src[line_start..line_end].trim_end();
+
+        let mut out = String::new();
+        if line_start > 0 {
+            let line_no = src[..line_start].matches('\n').count() + 1;
+            out.push_str(&format!("line {}:\n", line_no));
+        }
+        out.push_str(&format!("{}\n{}", line, self.caret(src)));
+        out
+    }
+
+    /// A caret under the offending token, to print below the line it's in,
+    /// followed by the message.
+    pub fn caret(&self, src: &str) -> String {
+        let start = self.span.start.min(src.len());
+        let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+        let col = src[line_start..start].chars().count();
+        let width = self.token.chars().count().max(1);
+        format!("{}{} {}", " ".repeat(col), "^".repeat(width), self)
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "; {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error {
+    /// The status a script run exits with after this error, so that shell
+    /// pipelines can tell the kinds of failure apart.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Quit => 0,
+            Error::IO(_) => 1,
+            Error::Syntax(_) => 3,
+            Error::Type => 4,
+            Error::Underflow(_) => 5,
+            Error::DivideByZero => 6,
+            Error::Overflow => 7,
+            Error::Recursion => 8,
+            Error::Domain(_) => 9,
+            Error::Unset(_) => 10,
+            Error::Limit(_) => 11,
+            Error::Native(_) => 12,
+            Error::Stopped => 13,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 /// Types of RPN calculator operations.
 pub enum Op {
     /// Adds two numbers: pop x, pop y, push x + y.
     Add,
+    /// Subtracts two numbers: pop y, pop x, push x - y.
+    Sub,
+    /// Multiplies two numbers: pop y, pop x, push x * y.
+    Mul,
+    /// Divides two numbers: pop y, pop x, push x / y (truncated).
+    Div,
+    /// Computes a remainder: pop y, pop x, push x % y.
+    Mod,
+    /// Raises to a power: pop y, pop x, push x ^ y.
+    Pow,
+    /// Compares two numbers: pop y, pop x, push x < y.
+    Lt,
+    /// Compares two numbers: pop y, pop x, push x > y.
+    Gt,
+    /// Compares two numbers: pop y, pop x, push x <= y.
+    Le,
+    /// Compares two numbers: pop y, pop x, push x >= y.
+    Ge,
     /// Checks equality of two values: pop x, pop y, push x == y.
     Eq,
     /// Negates a value: pop x, push ~x.
@@ -37,25 +518,141 @@ pub enum Op {
     /// Swaps two values: pop x, pop y, push x, push y.
     Swap,
     /// Computes a random number: pop x, push random number in [0, x).
+    /// x must be positive.
     Rand,
+    /// Reseeds the stack's random number generator: pop x, which must be an
+    /// integer. Like the bound of `#`, a `Big` fails with `Error::Overflow`
+    /// rather than `Error::Type`, since it is an integer, just too big.
+    Seed,
+    /// Duplicates the top value: ( x -- x x ).
+    Dup,
+    /// Discards the top value: ( x -- ).
+    Drop,
+    /// Copies the second value to the top: ( x y -- x y x ).
+    Over,
+    /// Rotates the third value to the top: ( x y z -- y z x ).
+    Rot,
+    /// Discards the second value: ( x y -- y ).
+    Nip,
+    /// Copies the top value below the second: ( x y -- y x y ).
+    Tuck,
+    /// Copies the nth value below the top to the top, counting from 0:
+    /// ( xn ... x0 n -- xn ... x0 xn ).
+    Pick,
+    /// Moves the nth value below the top to the top, counting from 0:
+    /// ( xn ... x0 n -- xn-1 ... x0 xn ).
+    Roll,
+    /// Pushes the number of values on the stack.
+    Depth,
+    /// Discards every value on the stack.
+    Clear,
+    /// Joins two strings, two lists or two quotations: pop y, pop x,
+    /// push x followed by y.
+    Concat,
+    /// Measures a string in characters, or a list in elements: pop x,
+    /// push its length.
+    Len,
     /// Quit the calculator.
     Quit,
 }
 
+impl Op {
+    /// Every operator, in the order they're listed by `words`.
+    pub const ALL: [Op; 28] = [
+        Op::Add, Op::Sub, Op::Mul, Op::Div, Op::Mod, Op::Pow,
+        Op::Lt, Op::Gt, Op::Le, Op::Ge, Op::Eq, Op::Neg,
+        Op::Swap, Op::Rand, Op::Seed,
+        Op::Dup, Op::Drop, Op::Over, Op::Rot, Op::Nip, Op::Tuck,
+        Op::Pick, Op::Roll, Op::Depth, Op::Clear,
+        Op::Concat, Op::Len,
+        Op::Quit,
+    ];
+
+    /// Looks up the operator written as `name`.
+    pub fn from_name(name: &str) -> Option<Op> {
+        Op::ALL.iter().copied().find(|op| op.name() == name)
+    }
+
+    /// The word for this operator, as written in a program.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Mod => "%",
+            Op::Pow => "^",
+            Op::Lt => "<",
+            Op::Gt => ">",
+            Op::Le => "<=",
+            Op::Ge => ">=",
+            Op::Eq => "=",
+            Op::Neg => "~",
+            Op::Swap => "<->",
+            Op::Rand => "#",
+            Op::Seed => "seed",
+            Op::Dup => "dup",
+            Op::Drop => "drop",
+            Op::Over => "over",
+            Op::Rot => "rot",
+            Op::Nip => "nip",
+            Op::Tuck => "tuck",
+            Op::Pick => "pick",
+            Op::Roll => "roll",
+            Op::Depth => "depth",
+            Op::Clear => "clear",
+            Op::Concat => "concat",
+            Op::Len => "len",
+            Op::Quit => "quit",
+        }
+    }
+}
+
 // TODO: Stack.
 // use a vec to simulate a stack
-#[derive(Debug)]
+/// The stack also holds the random number generator for `#`, so that a
+/// session can be replayed from a seed.
+///
+/// Clones of a stack share one generator. The REPL keeps clones to undo lines
+/// and to roll back lines that fail, and sharing means neither rewinds the
+/// generator: `10 # undo 10 #` draws two different numbers. For the same
+/// reason undoing `seed` doesn't undo the reseeding.
+#[derive(Clone)]
 pub struct Stack {
     stack: Vec<Elt>,
+    rng: Rc<RefCell<Box<dyn RngCore>>>,
+}
+
+impl fmt::Debug for Stack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Stack").field("stack", &self.stack).finish_non_exhaustive()
+    }
 }
 
 // TODO: Result.
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Stack {
     /// Creates a new Stack
     pub fn new() -> Stack {
-        Stack {stack: vec![]}
+        Stack::with_rng(StdRng::from_entropy())
+    }
+
+    /// Creates a new Stack whose random numbers are generated from `seed`.
+    pub fn with_seed(seed: u64) -> Stack {
+        Stack::with_rng(StdRng::seed_from_u64(seed))
+    }
+
+    /// Creates a new Stack which generates random numbers with `rng`, until
+    /// `seed` replaces it with a seeded `StdRng`.
+    pub fn with_rng(rng: impl RngCore + 'static) -> Stack {
+        Stack {stack: vec![], rng: Rc::new(RefCell::new(Box::new(rng)))}
     }
 
     /// Pushes a value onto the stack.
@@ -66,29 +663,107 @@ impl Stack {
 
     /// Tries to pop a value off of the stack.
     pub fn pop(&mut self) -> Result<Elt> {
-        self.stack.pop().ok_or(Error::Underflow)
+        self.stack.pop().ok_or(Error::Underflow("pop"))
     }
 
     /// Tries to read the last value of the stack
     pub fn last(&mut self) -> Result<&Elt> {
-        self.stack.last().ok_or(Error::Underflow)
+        self.stack.last().ok_or(Error::Underflow("pop"))
     }
 
-    /// Tries to evaluate an operator using values on the stack.
+    /// The number of values on the stack.
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// The values on the stack, from the bottom up.
+    pub fn as_slice(&self) -> &[Elt] {
+        &self.stack
+    }
+
+    /// Whether the stack holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    /// Fails with an underflow unless the stack holds at least `n` values, so
+    /// that an operator can check before it disturbs the stack.
+    fn require(&self, n: usize) -> Result<()> {
+        if self.stack.len() < n {
+            return Err(Error::Underflow("pop"));
+        }
+        Ok(())
+    }
+
+    /// Pops an index for `pick` or `roll`, which must name a value below it.
+    fn pop_index(&mut self) -> Result<usize> {
+        self.require(1)?;
+        let n = match self.stack.last() {
+            Some(Elt::Int(n)) => usize::try_from(*n).map_err(|_| Error::Type)?,
+            _ => return Err(Error::Type),
+        };
+        self.require(n + 2)?;
+        self.stack.pop();
+        Ok(self.stack.len() - 1 - n)
+    }
+
+    /// Tries to evaluate an operator using values on the stack. An underflow
+    /// is reported with the name of the operator.
     pub fn eval(&mut self, op: Op) -> Result<()> {
+        let name = op.name();
+        self.eval_op(op).map_err(|err| match err {
+            Error::Underflow(_) => Error::Underflow(name),
+            err => err,
+        })
+    }
+
+    fn eval_op(&mut self, op: Op) -> Result<()> {
         match op {
-            Op::Add => {
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod | Op::Pow => {
                 let y = self.pop()?;
                 let x = self.pop()?;
-                match (x, y) {
-                    (Elt::Int(x), Elt::Int(y)) => self.push(Elt::Int(x + y)),
-                    (_, _) => Err(Error::Type),
-                }
+                let res = match (&x, &y) {
+                    (Elt::Quantity(_), _) | (_, Elt::Quantity(_)) => quantity_arith(&op, &x, &y)?,
+                    _ => Pair::promote(&x, &y)?.arith(&op)?,
+                };
+                self.push(res)
+            }
+            Op::Lt | Op::Gt | Op::Le | Op::Ge => {
+                let y = self.pop()?;
+                let x = self.pop()?;
+                let ord = match (&x, &y) {
+                    (Elt::Quantity(a), Elt::Quantity(b)) if a.dimension() == b.dimension() => x.cmp(&y),
+                    (Elt::Quantity(_), _) | (_, Elt::Quantity(_)) => return Err(Error::Type),
+                    (x, y) if x.is_number() && y.is_number() => cmp_numbers(x, y),
+                    _ => return Err(Error::Type),
+                };
+                let res = match op {
+                    Op::Lt => ord.is_lt(),
+                    Op::Gt => ord.is_gt(),
+                    Op::Le => ord.is_le(),
+                    _ => ord.is_ge(),
+                };
+                self.push(Elt::Bool(res))
             }
             Op::Neg => {
                 match self.pop()? {
-                    Elt::Int(x) => self.push(Elt::Int(-x)),
+                    Elt::Int(x) => match x.checked_neg() {
+                        Some(x) => self.push(Elt::Int(x)),
+                        #[cfg(feature = "bigint")]
+                        None => self.push(Elt::Big(-BigInt::from(x))),
+                        #[cfg(not(feature = "bigint"))]
+                        None => Err(Error::Overflow),
+                    },
                     Elt::Bool(x) => self.push(Elt::Bool(!x)),
+                    #[cfg(feature = "bigint")]
+                    Elt::Big(x) => self.push(Elt::from_big(-x)),
+                    Elt::Rational(x) => {
+                        let x = x.checked_neg().ok_or(Error::Overflow)?;
+                        self.push(Elt::from_rational(x))
+                    }
+                    Elt::Float(x) => self.push(Elt::Float(-x)),
+                    Elt::Quantity(x) => self.push(Elt::Quantity(x.scale(-1.0))),
+                    _ => Err(Error::Type),
                 }
             }
             Op::Eq => {
@@ -106,13 +781,97 @@ impl Stack {
                 Ok(())
             }
             Op::Rand => {
+                match self.pop()? {
+                    Elt::Int(x) if x <= 0 => Err(Error::Domain("#")),
+                    Elt::Int(x) => {
+                        let x = self.rng.borrow_mut().gen_range(0..x);
+                        self.push(Elt::Int(x))
+                    }
+                    #[cfg(feature = "bigint")]
+                    Elt::Big(_) => Err(Error::Overflow),
+                    _ => Err(Error::Type),
+                }
+            }
+            Op::Seed => {
                 match self.pop()? {
                     Elt::Int(x) => {
-                        self.push(Elt::Int(rand::thread_rng().gen_range(0..x)))
+                        // Negative seeds are as good as any, so reinterpret them.
+                        *self.rng.borrow_mut() = Box::new(StdRng::seed_from_u64(x as i64 as u64));
+                        Ok(())
                     }
-                    Elt::Bool(_) => Err(Error::Type),
+                    #[cfg(feature = "bigint")]
+                    Elt::Big(_) => Err(Error::Overflow),
+                    _ => Err(Error::Type),
                 }
             }
+            Op::Dup => {
+                let x = self.last()?.clone();
+                self.push(x)
+            }
+            Op::Drop => self.pop().map(|_| ()),
+            Op::Over => {
+                self.require(2)?;
+                let x = self.stack[self.stack.len() - 2].clone();
+                self.push(x)
+            }
+            Op::Rot => {
+                self.require(3)?;
+                let x = self.stack.remove(self.stack.len() - 3);
+                self.push(x)
+            }
+            Op::Nip => {
+                self.require(2)?;
+                self.stack.remove(self.stack.len() - 2);
+                Ok(())
+            }
+            Op::Tuck => {
+                self.require(2)?;
+                let y = self.stack[self.stack.len() - 1].clone();
+                self.stack.insert(self.stack.len() - 2, y);
+                Ok(())
+            }
+            Op::Pick => {
+                let i = self.pop_index()?;
+                let x = self.stack[i].clone();
+                self.push(x)
+            }
+            Op::Roll => {
+                let i = self.pop_index()?;
+                let x = self.stack.remove(i);
+                self.push(x)
+            }
+            Op::Depth => {
+                let n = i32::try_from(self.stack.len()).map_err(|_| Error::Overflow)?;
+                self.push(Elt::Int(n))
+            }
+            Op::Clear => {
+                self.stack.clear();
+                Ok(())
+            }
+            Op::Concat => {
+                let y = self.pop()?;
+                let x = self.pop()?;
+                let res = match (x, y) {
+                    (Elt::Str(x), Elt::Str(y)) => Elt::Str(x + &y),
+                    (Elt::List(mut x), Elt::List(y)) => {
+                        x.extend(y);
+                        Elt::List(x)
+                    }
+                    (Elt::Quote(x), Elt::Quote(y)) => {
+                        Elt::Quote(Rc::new(x.iter().chain(y.iter()).cloned().collect()))
+                    }
+                    _ => return Err(Error::Type),
+                };
+                self.push(res)
+            }
+            Op::Len => {
+                let len = match self.pop()? {
+                    Elt::Str(x) => x.chars().count(),
+                    Elt::List(x) => x.len(),
+                    _ => return Err(Error::Type),
+                };
+                self.push(Elt::Int(i32::try_from(len).map_err(|_| Error::Overflow)?))
+            }
             Op::Quit => {
                 Err(Error::Quit)
             }
@@ -120,7 +879,131 @@ impl Stack {
     }
 }
 
+/// Applies one of the binary integer operators to `x` and `y`, failing with
+/// `Error::Overflow` if the result doesn't fit in an `i32`.
+fn int_arith(op: &Op, x: i32, y: i32) -> Result<i32> {
+    let res = match op {
+        Op::Add => x.checked_add(y),
+        Op::Sub => x.checked_sub(y),
+        Op::Mul => x.checked_mul(y),
+        Op::Div | Op::Mod if y == 0 => return Err(Error::DivideByZero),
+        Op::Div => x.checked_div(y),
+        Op::Mod => x.checked_rem(y),
+        // A negative exponent behaves like integer division: x ^ -y = 1 / x ^ y.
+        Op::Pow if y < 0 => match x {
+            0 => return Err(Error::DivideByZero),
+            1 => Some(1),
+            -1 => Some(if y % 2 == 0 { 1 } else { -1 }),
+            _ => Some(0),
+        },
+        Op::Pow => x.checked_pow(y as u32),
+        _ => unreachable!("not an arithmetic operator: {:?}", op),
+    };
+    res.ok_or(Error::Overflow)
+}
+
+/// The most bits a power of big integers may have. `pow` builds its result
+/// in a single step, so no step limit could stop a huge one.
+#[cfg(feature = "bigint")]
+pub const MAX_POW_BITS: u64 = 1 << 20;
+
+/// Applies one of the binary integer operators to big integers `x` and `y`.
+/// Only a power of more than `MAX_POW_BITS` bits overflows.
+#[cfg(feature = "bigint")]
+fn big_arith(op: &Op, x: &BigInt, y: &BigInt) -> Result<BigInt> {
+    let zero = BigInt::from(0);
+    match op {
+        Op::Add => Ok(x + y),
+        Op::Sub => Ok(x - y),
+        Op::Mul => Ok(x * y),
+        Op::Div | Op::Mod if *y == zero => Err(Error::DivideByZero),
+        Op::Div => Ok(x / y),
+        Op::Mod => Ok(x % y),
+        Op::Pow if *y < zero => match i32::try_from(x) {
+            // Only the parity of the exponent matters once it's negative.
+            Ok(x) => int_arith(op, x, if y % 2 == zero { -2 } else { -1 }).map(BigInt::from),
+            Err(_) => Ok(zero),
+        },
+        // Powers of 0, 1 and -1 stay small however large the exponent, and
+        // only its parity matters past 0.
+        Op::Pow if x.bits() <= 1 && *y > zero => Ok(if y % 2 == zero { x * x } else { x.clone() }),
+        Op::Pow => match u32::try_from(y) {
+            Ok(y) if x.bits().saturating_mul(u64::from(y)) <= MAX_POW_BITS => Ok(x.pow(y)),
+            _ => Err(Error::Overflow),
+        },
+        _ => unreachable!("not an arithmetic operator: {:?}", op),
+    }
+}
+
+/// Applies one of the binary arithmetic operators to fractions `x` and `y`.
+/// A fractional exponent can't be computed exactly, so it falls back to
+/// floating point.
+fn rational_arith(op: &Op, x: Rational, y: Rational) -> Result<Elt> {
+    let res = match op {
+        Op::Add => x.checked_add(y),
+        Op::Sub => x.checked_sub(y),
+        Op::Mul => x.checked_mul(y),
+        Op::Div | Op::Mod if y.numer() == 0 => return Err(Error::DivideByZero),
+        Op::Pow if x.numer() == 0 && y.numer() < 0 => return Err(Error::DivideByZero),
+        Op::Div => x.checked_div(y),
+        Op::Mod => x.checked_rem(y),
+        Op::Pow if !y.is_integer() => return float_arith(op, x.to_f64(), y.to_f64()).map(Elt::Float),
+        Op::Pow => x.checked_pow(i32::try_from(y.numer()).map_err(|_| Error::Overflow)?),
+        _ => unreachable!("not an arithmetic operator: {:?}", op),
+    };
+    res.map(Elt::from_rational).ok_or(Error::Overflow)
+}
+
+/// Applies one of the binary arithmetic operators when either operand is a
+/// quantity. Adding, subtracting or taking the remainder needs two quantities
+/// of the same dimension, and gives a result in the unit of `x`.
+/// Multiplying and dividing combine the dimensions, and a quantity may be
+/// raised to an integer power.
+fn quantity_arith(op: &Op, x: &Elt, y: &Elt) -> Result<Elt> {
+    match (op, x, y) {
+        (Op::Div | Op::Mod, _, y) if y.is_zero() => Err(Error::DivideByZero),
+        (Op::Add | Op::Sub | Op::Mod, Elt::Quantity(x), Elt::Quantity(y)) => {
+            let res = x.combine(y, |x, y| float_arith(op, x, y).expect("checked for zero"));
+            res.map(Elt::Quantity).ok_or(Error::Type)
+        }
+        (Op::Mul | Op::Div, Elt::Quantity(x), Elt::Quantity(y)) => {
+            let sign = if *op == Op::Mul { 1 } else { -1 };
+            match x.times(y, sign).ok_or(Error::Overflow)? {
+                Ok(res) => Ok(Elt::Quantity(res)),
+                // The units cancel out.
+                Err(res) => Ok(Elt::Float(res)),
+            }
+        }
+        (Op::Mul, Elt::Quantity(x), y) | (Op::Mul, y, Elt::Quantity(x)) => {
+            Ok(Elt::Quantity(x.scale(y.to_f64()?)))
+        }
+        (Op::Div, Elt::Quantity(x), y) => Ok(Elt::Quantity(x.scale(1.0 / y.to_f64()?))),
+        (Op::Div, x, Elt::Quantity(y)) => {
+            y.divide_into(x.to_f64()?).map(Elt::Quantity).ok_or(Error::Overflow)
+        }
+        (Op::Pow, Elt::Quantity(x), Elt::Int(n)) => x.pow(*n).map(Elt::Quantity).ok_or(Error::Overflow),
+        _ => Err(Error::Type),
+    }
+}
+
+/// Applies one of the binary arithmetic operators to floats `x` and `y`.
+/// Unlike IEEE division, dividing by zero is an error, as it is for integers.
+fn float_arith(op: &Op, x: f64, y: f64) -> Result<f64> {
+    match op {
+        Op::Add => Ok(x + y),
+        Op::Sub => Ok(x - y),
+        Op::Mul => Ok(x * y),
+        Op::Div | Op::Mod if y == 0.0 => Err(Error::DivideByZero),
+        Op::Div => Ok(x / y),
+        Op::Mod => Ok(x % y),
+        Op::Pow if x == 0.0 && y < 0.0 => Err(Error::DivideByZero),
+        Op::Pow => Ok(x.powf(y)),
+        _ => unreachable!("not an arithmetic operator: {:?}", op),
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod tests {
     use super::*;
 
@@ -130,7 +1013,7 @@ mod tests {
 
         let res = s.pop();
         assert!(res.is_err());
-        if let Err(Error::Underflow) = res { } else { assert!(false); }
+        if let Err(Error::Underflow("pop")) = res { } else { assert!(false); }
     }
 
     #[test]
@@ -143,7 +1026,7 @@ mod tests {
 
         let res = s.pop();
         assert!(res.is_err());
-        if let Err(Error::Underflow) = res { } else { assert!(false); }
+        if let Err(Error::Underflow("pop")) = res { } else { assert!(false); }
     }
 
     #[test]
@@ -227,7 +1110,7 @@ mod tests {
 
         let res = s.pop();
         assert!(res.is_err());
-        if let Err(Error::Underflow) = res { } else { assert!(false); }
+        if let Err(Error::Underflow("pop")) = res { } else { assert!(false); }
     }
 
     #[test]
@@ -237,7 +1120,7 @@ mod tests {
 
         let res = s.eval(Op::Swap);
         assert!(res.is_err());
-        if let Err(Error::Underflow) = res { } else { assert!(false); }
+        if let Err(Error::Underflow("<->")) = res { } else { assert!(false); }
     }
 
     #[test]
@@ -263,6 +1146,530 @@ mod tests {
         if let Err(Error::Type) = res { } else { assert!(false); }
     }
 
+    #[test]
+    fn test_eval_sub1() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(3)).unwrap();
+        s.push(Elt::Int(5)).unwrap();
+
+        assert!(s.eval(Op::Sub).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(-2));
+    }
+
+    #[test]
+    fn test_eval_mul1() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(6)).unwrap();
+        s.push(Elt::Int(7)).unwrap();
+
+        assert!(s.eval(Op::Mul).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(42));
+    }
+
+    #[test]
+    fn test_eval_div1() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(7)).unwrap();
+        s.push(Elt::Int(2)).unwrap();
+
+        assert!(s.eval(Op::Div).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(3));
+    }
+
+    #[test]
+    fn test_eval_div2() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(7)).unwrap();
+        s.push(Elt::Int(0)).unwrap();
+
+        let res = s.eval(Op::Div);
+        assert!(res.is_err());
+        if let Err(Error::DivideByZero) = res { } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_mod1() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(7)).unwrap();
+        s.push(Elt::Int(3)).unwrap();
+
+        assert!(s.eval(Op::Mod).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(1));
+    }
+
+    #[test]
+    fn test_eval_mod2() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(7)).unwrap();
+        s.push(Elt::Int(0)).unwrap();
+
+        let res = s.eval(Op::Mod);
+        assert!(res.is_err());
+        if let Err(Error::DivideByZero) = res { } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_pow1() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(2)).unwrap();
+        s.push(Elt::Int(10)).unwrap();
+
+        assert!(s.eval(Op::Pow).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(1024));
+    }
+
+    #[test]
+    fn test_eval_pow2() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(0)).unwrap();
+        s.push(Elt::Int(-1)).unwrap();
+
+        let res = s.eval(Op::Pow);
+        assert!(res.is_err());
+        if let Err(Error::DivideByZero) = res { } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_lt1() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(1)).unwrap();
+        s.push(Elt::Int(2)).unwrap();
+
+        assert!(s.eval(Op::Lt).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Bool(true));
+    }
+
+    #[test]
+    fn test_eval_ge1() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(1)).unwrap();
+        s.push(Elt::Int(2)).unwrap();
+
+        assert!(s.eval(Op::Ge).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Bool(false));
+    }
+
+    #[test]
+    fn test_eval_gt2() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(1)).unwrap();
+        s.push(Elt::Bool(true)).unwrap();
+
+        let res = s.eval(Op::Gt);
+        assert!(res.is_err());
+        if let Err(Error::Type) = res { } else { assert!(false); }
+    }
+
+    #[test]
+    #[cfg(not(feature = "bigint"))]
+    fn test_eval_overflow1() {
+        let ops = vec![
+            (i32::MAX, 1, Op::Add),
+            (i32::MIN, 1, Op::Sub),
+            (i32::MAX, 2, Op::Mul),
+            (i32::MIN, -1, Op::Div),
+            (i32::MIN, -1, Op::Mod),
+            (2, 31, Op::Pow),
+        ];
+        for (x, y, op) in ops {
+            let mut s = Stack::new();
+            s.push(Elt::Int(x)).unwrap();
+            s.push(Elt::Int(y)).unwrap();
+
+            let res = s.eval(op);
+            assert!(res.is_err());
+            if let Err(Error::Overflow) = res { } else { assert!(false); }
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "bigint"))]
+    fn test_eval_overflow2() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(i32::MIN)).unwrap();
+
+        let res = s.eval(Op::Neg);
+        assert!(res.is_err());
+        if let Err(Error::Overflow) = res { } else { assert!(false); }
+    }
+
+    #[test]
+    #[cfg(feature = "bigint")]
+    fn test_eval_bigint1() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(i32::MAX)).unwrap();
+        s.push(Elt::Int(1)).unwrap();
+
+        assert!(s.eval(Op::Add).is_ok());
+        assert_eq!(s.last().unwrap(), &Elt::Big(BigInt::from(i32::MAX) + 1));
+        assert!(s.last().unwrap() > &Elt::Int(i32::MAX));
+
+        s.push(Elt::Int(1)).unwrap();
+        assert!(s.eval(Op::Sub).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(i32::MAX));
+    }
+
+    #[test]
+    #[cfg(feature = "bigint")]
+    fn test_eval_bigint2() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(i32::MIN)).unwrap();
+
+        assert!(s.eval(Op::Neg).is_ok());
+        assert!(s.eval(Op::Neg).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(i32::MIN));
+
+        s.push(Elt::Big(-BigInt::from(i32::MAX) * 2)).unwrap();
+        s.push(Elt::Int(0)).unwrap();
+        assert!(s.eval(Op::Lt).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Bool(true));
+    }
+
+    #[test]
+    #[cfg(feature = "bigint")]
+    fn test_eval_bigint3() {
+        // Like `#`, `seed` takes an integer, but not one this big.
+        let mut s = Stack::new();
+        s.push(Elt::Big(BigInt::from(i32::MAX) * 2)).unwrap();
+
+        let res = s.eval(Op::Seed);
+        assert!(res.is_err());
+        if let Err(Error::Overflow) = res { } else { assert!(false); }
+    }
+
+    #[test]
+    #[cfg(feature = "bigint")]
+    fn test_eval_bigint_pow() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(2)).unwrap();
+        s.push(Elt::Int(1000)).unwrap();
+        assert!(s.eval(Op::Pow).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Big(BigInt::from(2).pow(1000)));
+
+        // Each would take gigabytes, in a step that can't be interrupted.
+        for (x, y) in [(10, 1_000_000_000), (2, i32::MAX)] {
+            s.push(Elt::Int(x)).unwrap();
+            s.push(Elt::Int(y)).unwrap();
+            assert!(matches!(s.eval(Op::Pow), Err(Error::Overflow)));
+            s = Stack::new();
+        }
+        s.push(Elt::Int(2)).unwrap();
+        s.push(Elt::Big(BigInt::from(4_000_000_000u32))).unwrap();
+        assert!(matches!(s.eval(Op::Pow), Err(Error::Overflow)));
+        s = Stack::new();
+
+        for (x, y, res) in [(1, 1i64 << 40, 1), (-1, (1 << 40) + 1, -1), (-1, 1 << 40, 1), (0, 1 << 40, 0)] {
+            s.push(Elt::Int(x)).unwrap();
+            s.push(Elt::Big(BigInt::from(y))).unwrap();
+            assert!(s.eval(Op::Pow).is_ok());
+            assert_eq!(s.pop().unwrap(), Elt::Int(res));
+        }
+    }
+
+    #[test]
+    fn test_eval_float1() {
+        let mut s = Stack::new();
+        s.push(Elt::Int(1)).unwrap();
+        s.push(Elt::Float(0.5)).unwrap();
+
+        assert!(s.eval(Op::Add).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float(1.5));
+    }
+
+    #[test]
+    fn test_eval_float2() {
+        let mut s = Stack::new();
+        s.push(Elt::Float(1.5)).unwrap();
+        s.push(Elt::Float(0.0)).unwrap();
+
+        let res = s.eval(Op::Div);
+        assert!(res.is_err());
+        if let Err(Error::DivideByZero) = res { } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_rational1() {
+        let mut s = Stack::new();
+        s.push(Elt::Rational(Rational::new(1, 3).unwrap())).unwrap();
+        s.push(Elt::Int(2)).unwrap();
+
+        assert!(s.eval(Op::Mul).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Rational(Rational::new(2, 3).unwrap()));
+    }
+
+    #[test]
+    fn test_eval_rational2() {
+        let mut s = Stack::new();
+        s.push(Elt::Rational(Rational::new(1, 2).unwrap())).unwrap();
+        s.push(Elt::Rational(Rational::new(1, 2).unwrap())).unwrap();
+
+        assert!(s.eval(Op::Add).is_ok());
+        let res = s.pop().unwrap();
+        assert!(matches!(res, Elt::Int(1)));
+    }
+
+    #[test]
+    fn test_eval_rational3() {
+        let mut s = Stack::new();
+        s.push(Elt::Rational(Rational::new(1, 4).unwrap())).unwrap();
+        s.push(Elt::Float(0.5)).unwrap();
+
+        assert!(s.eval(Op::Add).is_ok());
+        let res = s.pop().unwrap();
+        assert!(matches!(res, Elt::Float(x) if x == 0.75));
+    }
+
+    #[test]
+    fn test_eval_rational4() {
+        let mut s = Stack::new();
+        s.push(Elt::Rational(Rational::new(1, 4).unwrap())).unwrap();
+        s.push(Elt::Rational(Rational::new(1, 2).unwrap())).unwrap();
+
+        assert!(s.eval(Op::Pow).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Float(0.5));
+    }
+
+    #[test]
+    fn test_eval_cmp_mixed() {
+        let mut s = Stack::new();
+        s.push(Elt::Rational(Rational::new(1, 3).unwrap())).unwrap();
+        s.push(Elt::Float(0.3)).unwrap();
+
+        assert!(s.eval(Op::Gt).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Bool(true));
+
+        assert_eq!(Elt::Int(1), Elt::Float(1.0));
+        assert!(Elt::Bool(true) < Elt::Int(0));
+
+        // Compared exactly, not by promoting to the nearest float.
+        let third = Elt::Rational(Rational::new(1, 3).unwrap());
+        assert!(third > Elt::Float(1.0 / 3.0));
+        assert!(Elt::Rational(Rational::new(-1, 3).unwrap()) < Elt::Float(-1.0 / 3.0));
+        assert_eq!(Elt::Rational(Rational::new(5, 4).unwrap()), Elt::Float(1.25));
+        assert!(Elt::Float(1e300) > Elt::Rational(Rational::new(i64::MAX, 3).unwrap()));
+        assert!(Elt::Float(f64::MIN_POSITIVE / 4.0) > Elt::Int(0));
+        assert!(Elt::Float(-f64::MIN_POSITIVE / 4.0) < Elt::Rational(Rational::new(1, i64::MAX).unwrap()));
+        assert!(Elt::Float(f64::NEG_INFINITY) < Elt::Int(i32::MIN));
+    }
+
+    #[test]
+    #[cfg(feature = "bigint")]
+    fn test_eval_cmp_big() {
+        let big = |x: i64| Elt::Big(BigInt::from(x));
+        // 2^53 + 1 rounds to the float 2^53.
+        assert!(big((1 << 53) + 1) > Elt::Float((1u64 << 53) as f64));
+        assert!(big(-(1 << 53) - 1) < Elt::Float(-((1u64 << 53) as f64)));
+        assert_eq!(big(1 << 40), Elt::Float((1u64 << 40) as f64));
+        assert!(big(1 << 40) < Elt::Float((1u64 << 40) as f64 + 0.5));
+
+        let huge = Elt::Big(BigInt::from(i64::MAX) * 4);
+        assert!(huge > Elt::Rational(Rational::new(i64::MAX, 2).unwrap()));
+        assert!(Elt::Rational(Rational::new(-7, 2).unwrap()) < huge);
+    }
+
+    #[test]
+    fn test_eval_nan() {
+        let nan = Elt::Float(f64::NAN);
+        assert_eq!(nan, Elt::Float(f64::NAN));
+        assert!(nan > Elt::Float(f64::INFINITY));
+        assert!(nan > Elt::Int(i32::MAX));
+
+        let mut s = Stack::new();
+        s.push(Elt::Float(f64::NAN)).unwrap();
+        s.push(Elt::Float(f64::NAN)).unwrap();
+        assert!(s.eval(Op::Eq).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Bool(true));
+    }
+
+    fn stack_of(vals: &[i32]) -> Stack {
+        let mut s = Stack::new();
+        for &x in vals {
+            s.push(Elt::Int(x)).unwrap();
+        }
+        s
+    }
+
+    fn ints(s: &Stack) -> Vec<i32> {
+        s.stack.iter().map(|x| match x {
+            Elt::Int(x) => *x,
+            _ => panic!("not an integer: {:?}", x),
+        }).collect()
+    }
+
+    #[test]
+    fn test_eval_stack_words() {
+        let cases = vec![
+            (vec![1, 2], Op::Dup, vec![1, 2, 2]),
+            (vec![1, 2], Op::Drop, vec![1]),
+            (vec![1, 2], Op::Over, vec![1, 2, 1]),
+            (vec![1, 2, 3], Op::Rot, vec![2, 3, 1]),
+            (vec![1, 2], Op::Nip, vec![2]),
+            (vec![1, 2], Op::Tuck, vec![2, 1, 2]),
+            (vec![1, 2, 3, 0], Op::Pick, vec![1, 2, 3, 3]),
+            (vec![1, 2, 3, 2], Op::Pick, vec![1, 2, 3, 1]),
+            (vec![1, 2, 3, 2], Op::Roll, vec![2, 3, 1]),
+            (vec![1, 2, 3, 0], Op::Roll, vec![1, 2, 3]),
+            (vec![1, 2], Op::Depth, vec![1, 2, 2]),
+            (vec![], Op::Depth, vec![0]),
+            (vec![1, 2], Op::Clear, vec![]),
+        ];
+        for (before, op, after) in cases {
+            let mut s = stack_of(&before);
+            assert!(s.eval(op).is_ok());
+            assert_eq!(ints(&s), after);
+        }
+    }
+
+    #[test]
+    fn test_eval_stack_words_underflow() {
+        let cases = vec![
+            (vec![], Op::Dup),
+            (vec![], Op::Drop),
+            (vec![1], Op::Over),
+            (vec![1, 2], Op::Rot),
+            (vec![1], Op::Nip),
+            (vec![1], Op::Tuck),
+            (vec![], Op::Pick),
+            (vec![1, 2, 2], Op::Pick),
+            (vec![1, 2, 2], Op::Roll),
+        ];
+        for (before, op) in cases {
+            let name = op.name();
+            let mut s = stack_of(&before);
+            let res = s.eval(op);
+            assert!(res.is_err());
+            if let Err(Error::Underflow(word)) = res {
+                assert_eq!(word, name);
+            } else { assert!(false); }
+            // A failed word leaves the stack untouched.
+            assert_eq!(ints(&s), before);
+        }
+    }
+
+    #[test]
+    fn test_eval_pick_type() {
+        let mut s = stack_of(&[1, -1]);
+        let res = s.eval(Op::Pick);
+        assert!(res.is_err());
+        if let Err(Error::Type) = res { } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_eval_rand3() {
+        for x in [0, -5] {
+            let mut s = Stack::new();
+            s.push(Elt::Int(x)).unwrap();
+
+            let res = s.eval(Op::Rand);
+            assert!(res.is_err());
+            if let Err(Error::Domain("#")) = res { } else { assert!(false); }
+        }
+    }
+
+    #[test]
+    fn test_eval_rand_seeded() {
+        let rolls = |s: &mut Stack| -> Vec<Elt> {
+            (0..10).map(|_| {
+                s.push(Elt::Int(1000)).unwrap();
+                s.eval(Op::Rand).unwrap();
+                s.pop().unwrap()
+            }).collect()
+        };
+        let mut a = Stack::with_seed(42);
+        let mut b = Stack::with_seed(42);
+        assert_eq!(rolls(&mut a), rolls(&mut b));
+
+        // Reseeding starts the same sequence again.
+        let first = rolls(&mut Stack::with_seed(7));
+        a.push(Elt::Int(7)).unwrap();
+        assert!(a.eval(Op::Seed).is_ok());
+        assert_eq!(rolls(&mut a), first);
+    }
+
+    #[test]
+    fn test_eval_rand_shared() {
+        let roll = |s: &mut Stack| {
+            s.push(Elt::Int(1_000_000)).unwrap();
+            s.eval(Op::Rand).unwrap();
+            s.pop().unwrap()
+        };
+        // Any generator can be injected.
+        let mut s = Stack::with_rng(rand::rngs::mock::StepRng::new(0, 0));
+        assert_eq!(roll(&mut s), Elt::Int(0));
+
+        // Going back to a clone, as undo does, doesn't rewind the generator.
+        let mut a = Stack::with_seed(42);
+        let before = a.clone();
+        let first = roll(&mut a);
+        a = before;
+        assert_ne!(roll(&mut a), first);
+    }
+
+    #[test]
+    fn test_eval_concat_len() {
+        let mut s = Stack::new();
+        s.push(Elt::Str("héllo, ".to_string())).unwrap();
+        s.push(Elt::Str("world".to_string())).unwrap();
+        assert!(s.eval(Op::Concat).is_ok());
+        assert_eq!(s.last().unwrap(), &Elt::Str("héllo, world".to_string()));
+        assert!(s.eval(Op::Len).is_ok());
+        assert_eq!(s.pop().unwrap(), Elt::Int(12));
+
+        s.push(Elt::List(vec![Elt::Int(1)])).unwrap();
+        s.push(Elt::Str("x".to_string())).unwrap();
+        let res = s.eval(Op::Concat);
+        assert!(res.is_err());
+        if let Err(Error::Type) = res { } else { assert!(false); }
+    }
+
+    #[test]
+    fn test_display_values() {
+        let list = Elt::List(vec![Elt::Int(1), Elt::Str("a \"b\"\n".to_string())]);
+        assert_eq!(list.to_string(), r#"{ 1 "a \"b\"\n" }"#);
+        assert_eq!(Elt::List(vec![]).to_string(), "{ }");
+    }
+
+    #[test]
+    fn test_cmp_values() {
+        let a = Elt::Str("apple".to_string());
+        let b = Elt::Str("banana".to_string());
+        assert!(a < b);
+        assert!(Elt::Int(100) < a);
+        assert!(b < Elt::List(vec![]));
+        assert!(Elt::List(vec![Elt::Int(1)]) < Elt::List(vec![Elt::Int(1), Elt::Int(0)]));
+        assert_eq!(Elt::List(vec![Elt::Int(1)]), Elt::List(vec![Elt::Float(1.0)]));
+    }
+
+    #[test]
+    fn test_eval_quantity() {
+        let q = |text| Elt::Quantity(Quantity::parse(text).unwrap());
+        let eval = |x: Elt, y: Elt, op| {
+            let mut s = Stack::new();
+            s.push(x).unwrap();
+            s.push(y).unwrap();
+            s.eval(op).and_then(|()| s.pop())
+        };
+        assert_eq!(eval(q("1km"), q("500m"), Op::Add).unwrap().to_string(), "1.5km");
+        assert_eq!(eval(q("3m"), Elt::Int(2), Op::Mul).unwrap().to_string(), "6m");
+        assert_eq!(eval(q("6m"), q("2s"), Op::Div).unwrap().to_string(), "3m/s");
+        assert_eq!(eval(q("3m"), Elt::Int(2), Op::Pow).unwrap().to_string(), "9m^2");
+        assert_eq!(eval(q("1m"), q("100cm"), Op::Eq).unwrap(), Elt::Bool(true));
+        assert_eq!(eval(q("1KiB"), q("1kB"), Op::Gt).unwrap(), Elt::Bool(true));
+        for (x, y, op) in [
+            (q("5m"), q("3s"), Op::Add),
+            (q("5m"), Elt::Int(3), Op::Sub),
+            (q("5m"), q("3kg"), Op::Lt),
+            (q("5m"), Elt::Float(0.5), Op::Pow),
+        ] {
+            assert!(matches!(eval(x, y, op), Err(Error::Type)));
+        }
+        assert!(matches!(eval(q("5m"), q("0km"), Op::Mod), Err(Error::DivideByZero)));
+
+        assert_eq!(q("1km").convert(&Unit::parse("m").unwrap()).unwrap().to_string(), "1000m");
+        assert!(matches!(q("1km").convert(&Unit::parse("h").unwrap()), Err(Error::Type)));
+        assert!(Elt::Int(1) < q("1m") && q("1m") < Elt::Str(String::new()));
+    }
+
     #[test]
     fn test_eval_quit() {
         let mut s = Stack::new();
@@ -0,0 +1,356 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// The base dimensions that quantities are measured in.
+const DIMENSIONS: [&str; 4] = ["length", "time", "mass", "information"];
+
+/// The powers of the base dimensions in a quantity: `m/s` is length to the
+/// first power and time to the minus first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Dimension([i8; 4]);
+
+impl Dimension {
+    const LENGTH: Dimension = Dimension([1, 0, 0, 0]);
+    const TIME: Dimension = Dimension([0, 1, 0, 0]);
+    const MASS: Dimension = Dimension([0, 0, 1, 0]);
+    const INFORMATION: Dimension = Dimension([0, 0, 0, 1]);
+
+    /// Whether this is no dimension at all, as for a ratio of lengths.
+    pub fn is_none(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    /// Adds `power` times `other` to this dimension, or `None` on overflow.
+    fn add(self, other: Dimension, power: i8) -> Option<Dimension> {
+        let mut res = self.0;
+        for (x, y) in res.iter_mut().zip(other.0) {
+            *x = x.checked_add(y.checked_mul(power)?)?;
+        }
+        Some(Dimension(res))
+    }
+}
+
+impl fmt::Display for Dimension {
+    /// Writes the dimension like `length/time^2`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let terms = DIMENSIONS.iter().zip(self.0).map(|(&name, power)| (name, power));
+        write_terms(f, terms)
+    }
+}
+
+/// A unit, and how many of the base unit of its dimension it is.
+struct UnitDef {
+    symbol: &'static str,
+    scale: f64,
+    dimension: Dimension,
+}
+
+const fn unit(symbol: &'static str, scale: f64, dimension: Dimension) -> UnitDef {
+    UnitDef { symbol, scale, dimension }
+}
+
+/// The units that literals may be written in. The base units are `m`, `s`,
+/// `kg` and `B`.
+const UNITS: [UnitDef; 31] = [
+    unit("m", 1.0, Dimension::LENGTH),
+    unit("km", 1e3, Dimension::LENGTH),
+    unit("cm", 1e-2, Dimension::LENGTH),
+    unit("mm", 1e-3, Dimension::LENGTH),
+    unit("um", 1e-6, Dimension::LENGTH),
+    unit("nm", 1e-9, Dimension::LENGTH),
+    unit("in", 0.0254, Dimension::LENGTH),
+    unit("ft", 0.3048, Dimension::LENGTH),
+    unit("mi", 1609.344, Dimension::LENGTH),
+    unit("s", 1.0, Dimension::TIME),
+    unit("ms", 1e-3, Dimension::TIME),
+    unit("us", 1e-6, Dimension::TIME),
+    unit("ns", 1e-9, Dimension::TIME),
+    unit("min", 60.0, Dimension::TIME),
+    unit("h", 3600.0, Dimension::TIME),
+    unit("d", 86400.0, Dimension::TIME),
+    unit("kg", 1.0, Dimension::MASS),
+    unit("g", 1e-3, Dimension::MASS),
+    unit("mg", 1e-6, Dimension::MASS),
+    unit("t", 1e3, Dimension::MASS),
+    unit("lb", 0.45359237, Dimension::MASS),
+    unit("B", 1.0, Dimension::INFORMATION),
+    unit("bit", 0.125, Dimension::INFORMATION),
+    unit("kB", 1e3, Dimension::INFORMATION),
+    unit("MB", 1e6, Dimension::INFORMATION),
+    unit("GB", 1e9, Dimension::INFORMATION),
+    unit("TB", 1e12, Dimension::INFORMATION),
+    unit("KiB", 1024.0, Dimension::INFORMATION),
+    unit("MiB", 1048576.0, Dimension::INFORMATION),
+    unit("GiB", 1073741824.0, Dimension::INFORMATION),
+    unit("TiB", 1099511627776.0, Dimension::INFORMATION),
+];
+
+fn lookup(symbol: &str) -> Option<&'static UnitDef> {
+    UNITS.iter().find(|unit| unit.symbol == symbol)
+}
+
+/// A product of powers of units, like `km/h` or `kg*m/s^2`, which is what a
+/// quantity is shown in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unit {
+    /// The symbols of the units and their powers, none of which are zero.
+    terms: Vec<(&'static str, i8)>,
+}
+
+impl Unit {
+    /// Reads a unit written like `m`, `m/s^2` or `s^-1`, or returns `None`
+    /// if it isn't one. Each dimension may only appear once, since a unit
+    /// like `km*m` has no one symbol to show its length in: write `m^2`.
+    pub fn parse(text: &str) -> Option<Unit> {
+        let mut unit = Unit { terms: vec![] };
+        let mut sign = 1;
+        let mut rest = text;
+        loop {
+            let end = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+            let def = lookup(&rest[..end])?;
+            rest = &rest[end..];
+            let mut power = 1;
+            if let Some(exp) = rest.strip_prefix('^') {
+                let end = exp.find(['*', '/']).unwrap_or(exp.len());
+                power = i8::from_str(&exp[..end]).ok().filter(|&power| power != 0)?;
+                rest = &exp[end..];
+            }
+            let repeated = unit.terms.iter().any(|&(symbol, _)| lookup(symbol).unwrap().dimension == def.dimension);
+            if repeated {
+                return None;
+            }
+            unit.terms.push((def.symbol, power.checked_mul(sign)?));
+            match rest.chars().next() {
+                None => break,
+                Some('*') => sign = 1,
+                Some('/') => sign = -1,
+                Some(_) => return None,
+            }
+            rest = &rest[1..];
+        }
+        Some(unit).filter(|unit| unit.checked_dimension().is_some())
+    }
+
+    /// The dimension of the unit.
+    pub fn dimension(&self) -> Dimension {
+        self.checked_dimension().expect("units are checked when they're built")
+    }
+
+    fn checked_dimension(&self) -> Option<Dimension> {
+        self.terms.iter().try_fold(Dimension::default(), |dim, &(symbol, power)| {
+            dim.add(lookup(symbol)?.dimension, power)
+        })
+    }
+
+    /// How many base units this unit is.
+    pub fn scale(&self) -> f64 {
+        self.terms.iter().map(|&(symbol, power)| lookup(symbol).unwrap().scale.powi(power.into())).product()
+    }
+
+    /// Multiplies by `other` to the power `sign`, which is 1 to multiply and
+    /// -1 to divide. Returns `None` if a power overflows. Units of `other`
+    /// are replaced by units of the same dimension in this unit, so `km/h`
+    /// times `s` is `km`.
+    fn times(&self, other: &Unit, sign: i8) -> Option<Unit> {
+        let mut terms = self.terms.clone();
+        for &(symbol, power) in &other.terms {
+            let power = power.checked_mul(sign)?;
+            let dimension = lookup(symbol)?.dimension;
+            let same = |term: &&mut (&str, i8)| lookup(term.0).is_some_and(|unit| unit.dimension == dimension);
+            match terms.iter_mut().find(same) {
+                Some(term) => term.1 = term.1.checked_add(power)?,
+                None => terms.push((symbol, power)),
+            }
+        }
+        terms.retain(|term| term.1 != 0);
+        Some(Unit { terms }).filter(|unit| unit.checked_dimension().is_some())
+    }
+
+    /// Raises the unit to the power `n`, or returns `None` if it overflows.
+    fn pow(&self, n: i32) -> Option<Unit> {
+        let n = i8::try_from(n).ok()?;
+        let terms = self.terms.iter().map(|&(symbol, power)| Some((symbol, power.checked_mul(n)?)));
+        let unit = Unit { terms: terms.collect::<Option<_>>()? };
+        Some(unit).filter(|unit| !unit.terms.is_empty() && unit.checked_dimension().is_some())
+    }
+
+    /// Whether the unit has a dimension. Units like `km/m` don't.
+    fn has_dimension(&self) -> bool {
+        !self.dimension().is_none()
+    }
+}
+
+impl fmt::Display for Unit {
+    /// Writes the unit as `parse` reads it.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_terms(f, self.terms.iter().copied())
+    }
+}
+
+/// Writes powers of symbols like `a*b^2/c`, or `c^-1` if every power is
+/// negative. Zero powers are left out.
+fn write_terms<'a>(f: &mut fmt::Formatter, terms: impl Iterator<Item = (&'a str, i8)> + Clone) -> fmt::Result {
+    let mut sep = "";
+    for (symbol, power) in terms.clone().filter(|term| term.1 > 0) {
+        write!(f, "{}{}", sep, symbol)?;
+        if power != 1 {
+            write!(f, "^{}", power)?;
+        }
+        sep = "*";
+    }
+    let inverse = sep.is_empty();
+    for (symbol, power) in terms.filter(|term| term.1 < 0) {
+        match (inverse, power) {
+            (true, _) => write!(f, "{}{}^{}", sep, symbol, power)?,
+            (false, -1) => write!(f, "/{}", symbol)?,
+            (false, _) => write!(f, "/{}^{}", symbol, -power)?,
+        }
+        sep = "*";
+    }
+    Ok(())
+}
+
+/// A number of some unit, like `5m` or `3KiB`.
+///
+/// The value is kept in base units, so quantities of the same dimension
+/// compare and add whatever units they're shown in. Arithmetic on quantities
+/// returns `None` when their dimensions don't allow it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quantity {
+    /// The value in base units.
+    value: f64,
+    /// The unit to show the quantity in.
+    unit: Unit,
+}
+
+impl Quantity {
+    /// Creates a quantity of `value` in `unit`.
+    pub fn new(value: f64, unit: Unit) -> Quantity {
+        Quantity { value: value * unit.scale(), unit }
+    }
+
+    /// Reads a quantity literal: a number followed by a unit, like `5m`,
+    /// `1.5e3km/h` or `-2kg`.
+    pub fn parse(text: &str) -> Option<Quantity> {
+        // The number ends where the unit starts, at a letter, but an exponent
+        // may also have a letter, so try the last split first.
+        let splits = text.char_indices().filter(|&(_, c)| c.is_ascii_alphabetic()).map(|(i, _)| i);
+        for i in splits.collect::<Vec<_>>().into_iter().rev() {
+            let (number, unit) = text.split_at(i);
+            let number = match number {
+                "inf" | "-inf" | "NaN" => f64::from_str(number).ok(),
+                number if number.contains(|c: char| c.is_ascii_digit()) => f64::from_str(number).ok(),
+                _ => None,
+            };
+            if let (Some(value), Some(unit)) = (number, Unit::parse(unit)) {
+                return Some(Quantity::new(value, unit));
+            }
+        }
+        None
+    }
+
+    /// The value in base units.
+    pub fn base_value(&self) -> f64 {
+        self.value
+    }
+
+    pub fn dimension(&self) -> Dimension {
+        self.unit.dimension()
+    }
+
+    /// The same quantity shown in `unit`, if it has the same dimension.
+    pub fn to(&self, unit: &Unit) -> Option<Quantity> {
+        (unit.dimension() == self.dimension()).then(|| Quantity { value: self.value, unit: unit.clone() })
+    }
+
+    /// Adds `other`, or subtracts it, or takes the remainder by it, with
+    /// `f` on the values. The result is in this quantity's unit.
+    pub fn combine(&self, other: &Quantity, f: impl Fn(f64, f64) -> f64) -> Option<Quantity> {
+        (self.dimension() == other.dimension())
+            .then(|| Quantity { value: f(self.value, other.value), unit: self.unit.clone() })
+    }
+
+    /// Multiplies by `other` to the power `sign`, which is 1 to multiply and
+    /// -1 to divide. Returns `Err` with the plain number if the units cancel.
+    pub fn times(&self, other: &Quantity, sign: i8) -> Option<Result<Quantity, f64>> {
+        let value = if sign < 0 { self.value / other.value } else { self.value * other.value };
+        let unit = self.unit.times(&other.unit, sign)?;
+        if !unit.has_dimension() {
+            return Some(Err(value));
+        }
+        // The scales of units of the same dimension cancel, as in `km/m`,
+        // leaving a value in base units.
+        Some(Ok(Quantity { value, unit }))
+    }
+
+    /// Scales the quantity by a plain number.
+    pub fn scale(&self, x: f64) -> Quantity {
+        Quantity { value: self.value * x, unit: self.unit.clone() }
+    }
+
+    /// Divides a plain number by the quantity, or returns `None` if a power
+    /// of its unit overflows.
+    pub fn divide_into(&self, x: f64) -> Option<Quantity> {
+        Some(Quantity { value: x / self.value, unit: self.unit.pow(-1)? })
+    }
+
+    /// Raises the quantity to an integer power, or returns `None` if a power
+    /// of its unit overflows.
+    pub fn pow(&self, n: i32) -> Option<Quantity> {
+        Some(Quantity { value: self.value.powi(n), unit: self.unit.pow(n)? })
+    }
+}
+
+impl fmt::Display for Quantity {
+    /// Writes the quantity as a literal, in its unit.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.value / self.unit.scale(), self.unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quantity(text: &str) -> Quantity {
+        Quantity::parse(text).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(quantity("5m").base_value(), 5.0);
+        assert_eq!(quantity("3KiB").base_value(), 3072.0);
+        assert_eq!(quantity("1e3mm").base_value(), 1.0);
+        assert_eq!(quantity("-2min").base_value(), -120.0);
+        assert_eq!(quantity("36km/h").base_value(), 10.0);
+        assert_eq!(quantity("2kg*m/s^2").dimension(), Dimension([1, -2, 1, 0]));
+        for text in ["5", "m", "5x", "5m/m", "5m^0", "5m*", "1e3", "5km*m", "1m/s*s", "2h/min"] {
+            assert!(Quantity::parse(text).is_none(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        for text in ["5m", "1.5km/h", "2kg*m/s^2", "3s^-1*m^-2", "-0.5GiB"] {
+            assert_eq!(quantity(text).to_string(), text);
+        }
+        assert_eq!(quantity("1km").to(&Unit::parse("m").unwrap()).unwrap().to_string(), "1000m");
+        assert!(quantity("1km").to(&Unit::parse("s").unwrap()).is_none());
+        assert_eq!(Dimension([1, -2, 0, 0]).to_string(), "length/time^2");
+    }
+
+    #[test]
+    fn test_arith() {
+        let sum = quantity("1km").combine(&quantity("500m"), |x, y| x + y).unwrap();
+        assert_eq!(sum.to_string(), "1.5km");
+        assert!(quantity("1km").combine(&quantity("1s"), |x, y| x + y).is_none());
+
+        let speed = quantity("10m").times(&quantity("2s"), -1).unwrap().unwrap();
+        assert_eq!(speed.to_string(), "5m/s");
+        let distance = quantity("36km/h").times(&quantity("10s"), 1).unwrap().unwrap();
+        assert_eq!(distance.to_string(), "0.1km");
+        assert_eq!(quantity("1km").times(&quantity("10m"), -1), Some(Err(100.0)));
+        assert_eq!(quantity("2s").divide_into(1.0).unwrap().to_string(), "0.5s^-1");
+        assert_eq!(quantity("3m").pow(2).unwrap().to_string(), "9m^2");
+    }
+}
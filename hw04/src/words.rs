@@ -0,0 +1,159 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::interpreter::NativeOp;
+use crate::ast::{Block, Node};
+use crate::rpn::Elt;
+
+/// How deeply user-defined words and quotations may call each other before
+/// evaluation gives up with `Error::Recursion`.
+///
+/// Each call recurses on the native stack, several frames deep. In a debug
+/// build 1000 calls overflow the 2 MiB stack of a test thread before the limit
+/// is reached, which aborts the process instead of failing with an error, so
+/// the limit is kept well under that.
+pub const MAX_CALL_DEPTH: usize = 256;
+
+/// The user-defined words and variables, which live next to the `Stack` for a
+/// session.
+///
+/// A word's body is kept as the program it was defined with, and names in it
+/// are looked up when the word runs. So a word may call itself, and
+/// redefining a word changes every word that calls it.
+///
+/// Variables are set with `!name` and read with `@name`. They have a
+/// namespace of their own, so a variable may share a name with a word.
+///
+/// A program that embeds the calculator may also add native words, which are
+/// written in Rust. A user-defined word of the same name takes precedence.
+#[derive(Clone, Default)]
+pub struct Dictionary {
+    words: HashMap<String, Rc<Vec<Node>>>,
+    vars: HashMap<String, Elt>,
+    natives: HashMap<String, Rc<dyn NativeOp>>,
+}
+
+impl Dictionary {
+    /// Creates an empty dictionary.
+    pub fn new() -> Dictionary {
+        Dictionary { words: HashMap::new(), vars: HashMap::new(), natives: HashMap::new() }
+    }
+
+    /// Defines `name` as `body`, replacing any previous definition.
+    pub fn define(&mut self, name: &str, body: Vec<Node>) {
+        self.words.insert(name.to_string(), Rc::new(body));
+    }
+
+    /// Looks up the body of a word.
+    pub fn get(&self, name: &str) -> Option<Rc<Vec<Node>>> {
+        self.words.get(name).cloned()
+    }
+
+    /// The names of every defined word, in alphabetical order.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.words.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    /// Defines `name` as a native word, replacing any previous native word.
+    pub fn define_native(&mut self, name: &str, op: Rc<dyn NativeOp>) {
+        self.natives.insert(name.to_string(), op);
+    }
+
+    /// Looks up a native word.
+    pub fn native(&self, name: &str) -> Option<Rc<dyn NativeOp>> {
+        self.natives.get(name).cloned()
+    }
+
+    /// The names of every native word, in alphabetical order.
+    pub fn native_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.natives.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    /// Sets the variable `name` to `val`, replacing any previous value.
+    pub fn store(&mut self, name: &str, val: Elt) {
+        self.vars.insert(name.to_string(), val);
+    }
+
+    /// Reads the variable `name`, or `None` if it was never set.
+    pub fn fetch(&self, name: &str) -> Option<&Elt> {
+        self.vars.get(name)
+    }
+
+    /// Every variable and its value, in alphabetical order.
+    pub fn vars(&self) -> Vec<(&str, &Elt)> {
+        let mut vars: Vec<(&str, &Elt)> =
+            self.vars.iter().map(|(name, val)| (name.as_str(), val)).collect();
+        vars.sort_by(|a, b| a.0.cmp(b.0));
+        vars
+    }
+}
+
+impl fmt::Debug for Dictionary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Dictionary")
+            .field("words", &self.words)
+            .field("vars", &self.vars)
+            .field("natives", &self.native_names())
+            .finish()
+    }
+}
+
+impl fmt::Display for Dictionary {
+    /// Writes the words and variables as a program that defines them again,
+    /// one to a line. Parsing checks every name, so a word that an earlier
+    /// word calls is first declared with an empty body. Native words are left
+    /// out, since only the embedding program can define them.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self.names();
+        let mut declared: HashSet<&str> = HashSet::new();
+        for (i, name) in names.iter().enumerate() {
+            let mut called = HashSet::new();
+            calls(&self.words[*name], &mut called);
+            declared.extend(names[i + 1..].iter().filter(|later| called.contains(*later)));
+        }
+        for name in &names {
+            if declared.contains(name) {
+                writeln!(f, ": {} ;", name)?;
+            }
+        }
+        for name in &names {
+            writeln!(f, ": {} {};", name, Block(&self.words[*name]))?;
+        }
+        for (name, val) in self.vars() {
+            writeln!(f, "{} !{}", val, name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the names of the words that `body` runs, including in nested
+/// blocks, quotations and lists.
+fn calls<'a>(body: &'a [Node], names: &mut HashSet<&'a str>) {
+    for node in body {
+        match node {
+            Node::Word(name) => {
+                names.insert(name);
+            }
+            Node::Push(val) => calls_in(val, names),
+            Node::If(then, els) => {
+                calls(then, names);
+                calls(els, names);
+            }
+            Node::Until(body) | Node::Loop(body) | Node::Define(_, body) => calls(body, names),
+            _ => {}
+        }
+    }
+}
+
+fn calls_in<'a>(val: &'a Elt, names: &mut HashSet<&'a str>) {
+    match val {
+        Elt::Quote(body) => calls(body, names),
+        Elt::List(vals) => vals.iter().for_each(|val| calls_in(val, names)),
+        _ => {}
+    }
+}
@@ -0,0 +1,497 @@
+//! Property-based tests of `rpn::Stack`. Random programs, both well-typed and
+//! ill-typed, are checked against the stack effects of the operators and
+//! against a reference evaluator. A failing program is shrunk to a minimal
+//! one, and printed as a line that can be pasted into the REPL.
+
+use std::fmt;
+use std::rc::Rc;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::sample::select;
+
+use hw04::ast::parse_number;
+use hw04::parser::parse;
+use hw04::rpn::{Elt, Error, Op, Result, Stack};
+use hw04::words::Dictionary;
+
+#[derive(Clone)]
+enum Step {
+    Push(Elt),
+    Op(Op),
+}
+
+impl Step {
+    fn run(&self, stack: &mut Stack) -> Result<()> {
+        match self {
+            Step::Push(val) => stack.push(val.clone()),
+            Step::Op(op) => stack.eval(*op),
+        }
+    }
+}
+
+impl fmt::Debug for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Push(val) => write!(f, "{}", val),
+            Step::Op(op) => write!(f, "{}", op.name()),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Program(Vec<Step>);
+
+impl fmt::Debug for Program {
+    /// Writes the program as it would be typed, so that a failing case can be
+    /// run by hand.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let steps: Vec<String> = self.0.iter().map(|step| step.to_string()).collect();
+        write!(f, "`{}`", steps.join(" "))
+    }
+}
+
+/// Integers near zero, and the ones that arithmetic tends to get wrong.
+fn int() -> impl Strategy<Value = i32> {
+    prop_oneof![2 => -10..=10, 1 => select(vec![0, -1, i32::MAX, i32::MIN])]
+}
+
+/// The values the reference evaluator knows: integers, booleans and strings.
+fn plain_elt() -> impl Strategy<Value = Elt> {
+    prop_oneof![
+        3 => int().prop_map(Elt::Int),
+        1 => any::<bool>().prop_map(Elt::Bool),
+        1 => "[aß]{0,2}".prop_map(Elt::Str),
+    ]
+}
+
+/// Values of every type.
+fn any_elt() -> impl Strategy<Value = Elt> {
+    let numbers = vec!["1/3", "-7/2", "0.5", "-0.0", "NaN", "inf", "5m", "3KiB", "9.8m/s^2", "2s"];
+    let quotes = vec!["", "1 +", "dup *"];
+    prop_oneof![
+        4 => plain_elt(),
+        2 => select(numbers).prop_map(|x| parse_number(x).expect("not a number")),
+        1 => vec(int(), 0..3).prop_map(|xs| Elt::List(xs.into_iter().map(Elt::Int).collect())),
+        1 => select(quotes).prop_map(|src| {
+            Elt::Quote(Rc::new(parse(src, &Dictionary::new()).expect("not a program")))
+        }),
+    ]
+}
+
+/// Programs that push `vals` and run any of `ops`, whether or not they fit.
+fn program(vals: impl Strategy<Value = Elt>, ops: Vec<Op>) -> impl Strategy<Value = Program> {
+    let step = prop_oneof![vals.prop_map(Step::Push), select(ops).prop_map(Step::Op)];
+    vec(step, 0..32).prop_map(Program)
+}
+
+/// Every operator but `#`, whose results can't be predicted.
+fn predictable_ops() -> Vec<Op> {
+    Op::ALL.iter().copied().filter(|&op| op != Op::Rand).collect()
+}
+
+/// The index that `pick` or `roll` would pop, if the top value is one.
+fn index(top: Option<&Elt>) -> Option<usize> {
+    match top {
+        Some(Elt::Int(n)) => usize::try_from(*n).ok(),
+        _ => None,
+    }
+}
+
+/// How many values `op` takes from a stack `depth` deep, and how many it
+/// leaves in their place. `index` is the top value, if it could be an index.
+fn effect(op: Op, depth: usize, index: Option<usize>) -> (usize, usize) {
+    match op {
+        Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod | Op::Pow
+        | Op::Lt | Op::Gt | Op::Le | Op::Ge | Op::Eq | Op::Concat | Op::Nip => (2, 1),
+        Op::Neg | Op::Rand | Op::Len => (1, 1),
+        Op::Seed | Op::Drop => (1, 0),
+        Op::Dup => (1, 2),
+        Op::Swap => (2, 2),
+        Op::Over | Op::Tuck => (2, 3),
+        Op::Rot => (3, 3),
+        // The index, then the values down to the one it names.
+        Op::Pick => index.map_or((1, 1), |n| (n + 2, n + 2)),
+        Op::Roll => index.map_or((1, 1), |n| (n + 2, n + 1)),
+        Op::Depth => (0, 1),
+        Op::Clear => (depth, 0),
+        Op::Quit => (0, 0),
+    }
+}
+
+/// Rearranges `stack` as one of the operators that only move values around
+/// does, or returns false if `op` isn't one. The stack must be deep enough.
+fn shuffle<T: Clone>(op: Op, stack: &mut Vec<T>) -> bool {
+    let n = stack.len();
+    match op {
+        Op::Swap => stack.swap(n - 2, n - 1),
+        Op::Dup => stack.push(stack[n - 1].clone()),
+        Op::Drop => {
+            stack.pop();
+        }
+        Op::Over => stack.push(stack[n - 2].clone()),
+        Op::Rot => {
+            let x = stack.remove(n - 3);
+            stack.push(x);
+        }
+        Op::Nip => {
+            stack.remove(n - 2);
+        }
+        Op::Tuck => stack.insert(n - 2, stack[n - 1].clone()),
+        Op::Clear => stack.clear(),
+        _ => return false,
+    }
+    true
+}
+
+/// Whether running `step` could build an integer too big to compute in a
+/// test. Only the `bigint` feature lets integers grow, so until a program
+/// makes a `Big`, it is tested, and then it stops.
+fn runs_away(step: &Step, stack: &Stack) -> bool {
+    let Step::Op(op) = step else { return false };
+    let arith = matches!(op, Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod | Op::Pow);
+    let vals = stack.as_slice();
+    let operands = &vals[vals.len().saturating_sub(2)..];
+    let big = operands.iter().any(|val| val.type_name() == "Big");
+    cfg!(feature = "bigint") && arith && big
+}
+
+/// A value of the reference evaluator.
+#[derive(Clone, Debug, PartialEq)]
+enum Val {
+    Int(i32),
+    Bool(bool),
+    Str(String),
+}
+
+impl Val {
+    fn from_elt(val: &Elt) -> Val {
+        match val {
+            Elt::Int(x) => Val::Int(*x),
+            Elt::Bool(x) => Val::Bool(*x),
+            Elt::Str(x) => Val::Str(x.clone()),
+            val => panic!("no reference value for {}", val),
+        }
+    }
+
+    fn to_elt(&self) -> Elt {
+        match self {
+            Val::Int(x) => Elt::Int(*x),
+            Val::Bool(x) => Elt::Bool(*x),
+            Val::Str(x) => Elt::Str(x.clone()),
+        }
+    }
+}
+
+/// The exact result of an integer operator, or `None` if it's too big to
+/// hold.
+fn exact(op: Op, x: i128, y: i128) -> Result<Option<i128>> {
+    match op {
+        Op::Add => Ok(Some(x + y)),
+        Op::Sub => Ok(Some(x - y)),
+        Op::Mul => Ok(Some(x * y)),
+        Op::Div | Op::Mod if y == 0 => Err(Error::DivideByZero),
+        Op::Div => Ok(Some(x / y)),
+        // Like `i32::MIN / -1`, `i32::MIN % -1` overflows, though the
+        // remainder, 0, would fit.
+        Op::Mod if x == i32::MIN.into() && y == -1 => Ok(None),
+        Op::Mod => Ok(Some(x % y)),
+        // 1 / x ^ -y, truncated.
+        Op::Pow if y < 0 && x == 0 => Err(Error::DivideByZero),
+        Op::Pow if y < 0 => Ok(Some(if x.abs() != 1 { 0 } else { x.pow((y % 2).unsigned_abs() as u32) })),
+        Op::Pow => Ok(x.checked_pow(y as u32)),
+        _ => unreachable!("not an arithmetic operator: {:?}", op),
+    }
+}
+
+/// Runs one step of a program on integers, booleans and strings, as simply
+/// as it can be done, to compare `Stack` with. Returns `None` if the step
+/// makes an integer that doesn't fit in an `i32`, which is only allowed with
+/// the `bigint` feature.
+fn reference(stack: &mut Vec<Val>, step: &Step) -> Option<Result<()>> {
+    let op = match step {
+        Step::Push(val) => {
+            stack.push(Val::from_elt(val));
+            return Some(Ok(()));
+        }
+        Step::Op(op) => *op,
+    };
+    let index = match stack.last() {
+        Some(Val::Int(n)) => usize::try_from(*n).ok(),
+        _ => None,
+    };
+    let (takes, _) = effect(op, stack.len(), index);
+    if stack.len() < takes {
+        return Some(Err(Error::Underflow(op.name())));
+    }
+    if shuffle(op, stack) {
+        return Some(Ok(()));
+    }
+    let int = |x: Option<i128>| match x.and_then(|x| i32::try_from(x).ok()) {
+        Some(x) => Some(Ok(Val::Int(x))),
+        None if cfg!(feature = "bigint") => None,
+        None => Some(Err(Error::Overflow)),
+    };
+    let res = match op {
+        Op::Pick | Op::Roll => match index {
+            Some(n) => {
+                stack.pop();
+                let i = stack.len() - 1 - n;
+                Ok(if op == Op::Pick { stack[i].clone() } else { stack.remove(i) })
+            }
+            None => Err(Error::Type),
+        },
+        Op::Depth => Ok(Val::Int(stack.len() as i32)),
+        Op::Quit => Err(Error::Quit),
+        Op::Neg => match stack.pop().unwrap() {
+            Val::Int(x) => int(Some(-(x as i128)))?,
+            Val::Bool(x) => Ok(Val::Bool(!x)),
+            Val::Str(_) => Err(Error::Type),
+        },
+        Op::Len => match stack.pop().unwrap() {
+            Val::Str(x) => Ok(Val::Int(x.chars().count() as i32)),
+            _ => Err(Error::Type),
+        },
+        Op::Seed => match stack.pop().unwrap() {
+            Val::Int(_) => return Some(Ok(())),
+            _ => Err(Error::Type),
+        },
+        _ => {
+            let y = stack.pop().unwrap();
+            let x = stack.pop().unwrap();
+            match (op, x, y) {
+                (Op::Eq, x, y) => Ok(Val::Bool(x == y)),
+                (Op::Concat, Val::Str(x), Val::Str(y)) => Ok(Val::Str(x + &y)),
+                (Op::Lt, Val::Int(x), Val::Int(y)) => Ok(Val::Bool(x < y)),
+                (Op::Gt, Val::Int(x), Val::Int(y)) => Ok(Val::Bool(x > y)),
+                (Op::Le, Val::Int(x), Val::Int(y)) => Ok(Val::Bool(x <= y)),
+                (Op::Ge, Val::Int(x), Val::Int(y)) => Ok(Val::Bool(x >= y)),
+                (Op::Concat | Op::Lt | Op::Gt | Op::Le | Op::Ge, _, _) => Err(Error::Type),
+                (op, Val::Int(x), Val::Int(y)) => match exact(op, x.into(), y.into()) {
+                    Ok(x) => int(x)?,
+                    Err(err) => Err(err),
+                },
+                _ => Err(Error::Type),
+            }
+        }
+    };
+    Some(res.map(|val| stack.push(val)))
+}
+
+/// What an element of the stack can be in a well-typed program.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Int,
+    Bool,
+    Str,
+}
+
+impl Kind {
+    fn of(val: &Elt) -> Kind {
+        match val.type_name() {
+            "Int" | "Big" => Kind::Int,
+            "Bool" => Kind::Bool,
+            "Str" => Kind::Str,
+            name => panic!("unexpected {}", name),
+        }
+    }
+}
+
+/// Builds a program that never fails with `Error::Type` or
+/// `Error::Underflow`, along with the kinds of value it leaves, by choosing
+/// each step from the ones that fit the stack so far.
+///
+/// Each choice picks a step, and what's left of it picks a value to push,
+/// so shrinking the choices shrinks the program and keeps it well-typed.
+fn well_typed(choices: &[u32]) -> (Program, Vec<Kind>) {
+    const INTS: [i32; 8] = [0, 1, -1, 2, 7, -5, i32::MAX, i32::MIN];
+    const STRS: [&str; 3] = ["", "a", "ßa"];
+    let mut steps = vec![];
+    let mut kinds = vec![];
+    for &choice in choices {
+        let top: Vec<Kind> = kinds.iter().rev().take(2).copied().collect();
+        let mut ops: Vec<Op> = Op::ALL
+            .iter()
+            .copied()
+            .filter(|&op| {
+                let (takes, _) = effect(op, kinds.len(), None);
+                kinds.len() >= takes
+                    && match op {
+                        Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod | Op::Pow
+                        | Op::Lt | Op::Gt | Op::Le | Op::Ge => top == [Kind::Int, Kind::Int],
+                        Op::Neg => top[0] != Kind::Str,
+                        Op::Rand | Op::Seed => top[0] == Kind::Int,
+                        Op::Concat => top == [Kind::Str, Kind::Str],
+                        Op::Len => top[0] == Kind::Str,
+                        Op::Quit => false,
+                        _ => true,
+                    }
+            })
+            .collect();
+        // Pushing comes first, so that choices shrink to pushes.
+        let pushes = 3;
+        let n = pushes + ops.len();
+        let (which, value) = ((choice as usize) % n, (choice as usize) / n);
+        match which {
+            0 => {
+                steps.push(Step::Push(Elt::Int(INTS[value % INTS.len()])));
+                kinds.push(Kind::Int);
+            }
+            1 => {
+                steps.push(Step::Push(Elt::Bool(value % 2 == 1)));
+                kinds.push(Kind::Bool);
+            }
+            2 => {
+                steps.push(Step::Push(Elt::Str(STRS[value % STRS.len()].to_string())));
+                kinds.push(Kind::Str);
+            }
+            _ => {
+                let op = ops.swap_remove(which - pushes);
+                if let Op::Pick | Op::Roll = op {
+                    // Push an index in range first.
+                    let n = value % kinds.len();
+                    steps.push(Step::Push(Elt::Int(n as i32)));
+                    let i = kinds.len() - 1 - n;
+                    let kind = if op == Op::Pick { kinds[i] } else { kinds.remove(i) };
+                    kinds.push(kind);
+                } else if !shuffle(op, &mut kinds) {
+                    let (takes, leaves) = effect(op, kinds.len(), None);
+                    let kind = match op {
+                        Op::Lt | Op::Gt | Op::Le | Op::Ge | Op::Eq => Kind::Bool,
+                        Op::Neg | Op::Concat => kinds[kinds.len() - 1],
+                        _ => Kind::Int,
+                    };
+                    kinds.truncate(kinds.len() - takes);
+                    kinds.extend(std::iter::repeat_n(kind, leaves));
+                }
+                steps.push(Step::Op(op));
+            }
+        }
+    }
+    (Program(steps), kinds)
+}
+
+/// A stack holding `vals`, from the bottom up.
+fn stack_of(vals: &[Elt]) -> Stack {
+    let mut stack = Stack::with_seed(0);
+    for val in vals {
+        stack.push(val.clone()).unwrap();
+    }
+    stack
+}
+
+/// The values, with their types, so that `1` and `1.0` differ.
+fn show(vals: &[Elt]) -> String {
+    format!("{:?}", vals)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2048))]
+
+    #[test]
+    fn depth_follows_stack_effects(program in program(any_elt(), Op::ALL.to_vec())) {
+        let mut stack = Stack::with_seed(0);
+        for step in &program.0 {
+            if runs_away(step, &stack) {
+                break;
+            }
+            let depth = stack.len();
+            let (takes, leaves) = match step {
+                Step::Push(_) => (0, 1),
+                Step::Op(op) => effect(*op, depth, index(stack.as_slice().last())),
+            };
+            match step.run(&mut stack) {
+                Ok(()) => {
+                    prop_assert!(depth >= takes);
+                    prop_assert_eq!(stack.len(), depth - takes + leaves);
+                }
+                Err(Error::Underflow(name)) => {
+                    prop_assert!(depth < takes);
+                    prop_assert_eq!(name, step.to_string());
+                    break;
+                }
+                Err(_) => {
+                    prop_assert!(depth >= takes);
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn double_negation_is_identity(vals in vec(any_elt(), 0..6)) {
+        let mut stack = stack_of(&vals);
+        let res = stack.eval(Op::Neg).and_then(|()| stack.eval(Op::Neg));
+        match vals.last() {
+            None => prop_assert!(matches!(res, Err(Error::Underflow("~")))),
+            Some(Elt::Str(_) | Elt::List(_) | Elt::Quote(_)) => prop_assert!(matches!(res, Err(Error::Type))),
+            Some(Elt::Int(i32::MIN)) if !cfg!(feature = "bigint") => {
+                prop_assert!(matches!(res, Err(Error::Overflow)))
+            }
+            Some(_) => {
+                prop_assert!(res.is_ok(), "{:?}", res);
+                prop_assert_eq!(show(stack.as_slice()), show(&vals));
+            }
+        }
+    }
+
+    #[test]
+    fn double_swap_is_identity(vals in vec(any_elt(), 0..6)) {
+        let mut stack = stack_of(&vals);
+        let res = stack.eval(Op::Swap).and_then(|()| stack.eval(Op::Swap));
+        if vals.len() < 2 {
+            prop_assert!(matches!(res, Err(Error::Underflow("<->"))));
+        } else {
+            prop_assert!(res.is_ok(), "{:?}", res);
+            prop_assert_eq!(show(stack.as_slice()), show(&vals));
+        }
+    }
+
+    #[test]
+    fn well_typed_programs_only_fail_on_values(
+        (program, kinds) in vec(any::<u32>(), 0..48).prop_map(|choices| well_typed(&choices))
+    ) {
+        let mut stack = Stack::with_seed(0);
+        let mut finished = true;
+        for step in &program.0 {
+            if runs_away(step, &stack) {
+                finished = false;
+                break;
+            }
+            if let Err(err) = step.run(&mut stack) {
+                prop_assert!(
+                    matches!(err, Error::DivideByZero | Error::Overflow | Error::Domain(_)),
+                    "{} failed with {:?}", step, err
+                );
+                finished = false;
+                break;
+            }
+        }
+        if finished {
+            let found: Vec<Kind> = stack.as_slice().iter().map(Kind::of).collect();
+            prop_assert_eq!(found, kinds);
+        }
+    }
+
+    #[test]
+    fn matches_reference(program in program(plain_elt(), predictable_ops())) {
+        let mut stack = Stack::with_seed(0);
+        let mut model = vec![];
+        for step in &program.0 {
+            let Some(expected) = reference(&mut model, step) else { break };
+            let res = step.run(&mut stack);
+            prop_assert_eq!(format!("{:?}", res), format!("{:?}", expected), "at {}", step);
+            if res.is_err() {
+                break;
+            }
+            let model: Vec<Elt> = model.iter().map(Val::to_elt).collect();
+            prop_assert_eq!(show(stack.as_slice()), show(&model));
+        }
+    }
+}
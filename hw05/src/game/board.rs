@@ -1,103 +1,246 @@
 use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::io::Read;
 use std::rc::Rc;
 use std::result;
 
-use rustc_serialize::json::Json;
+use rand::Rng;
+use rustc_serialize::json::{Json, ParserError, ToJson};
 
 use super::curio::Curio;
 use super::hall::Hall;
 use super::room::Room;
 
-pub type Result<T> = result::Result<T, String>;
+pub type Result<T> = result::Result<T, Error>;
+
+/// The most curios a room may count. Each is generated when the board is
+/// read, so a huge count would exhaust memory.
+pub const MAX_CURIOS: u64 = 1000;
+
+/// Why a board couldn't be loaded.
+#[derive(Debug)]
+pub enum Error {
+    /// The input isn't JSON.
+    Json(ParserError),
+    /// The input is JSON, but doesn't describe a valid board. Holds every
+    /// problem found, in the order they appear in the input.
+    Invalid(Vec<Problem>),
+}
+
+/// Something wrong with one part of a board description.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    /// Where the problem is, as a JSON path like `$.rooms[2].name`.
+    pub path: String,
+    pub kind: ProblemKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProblemKind {
+    /// A required field is missing.
+    Missing,
+    /// A value has the wrong type. Names the type it should have.
+    WrongType(&'static str),
+    /// A room has the same name as an earlier one, ignoring case, so `go`
+    /// couldn't tell them apart. Holds the name and the earlier room.
+    DuplicateName(String, usize),
+    /// A hall doesn't connect exactly two rooms. Holds how many it lists.
+    HallLength(usize),
+    /// A hall, or the player, is in a room that doesn't exist. Holds the
+    /// room number.
+    NoSuchRoom(u64),
+    /// A hall connects a room to itself.
+    SelfLoop,
+    /// No path of halls leads to the room from the first room, where the
+    /// player starts. Holds the room's name.
+    Unreachable(String),
+    /// A room counts more curios than `MAX_CURIOS`. Holds the count.
+    TooManyCurios(u64),
+    /// The first room, where the player starts, has a pit or bats.
+    HazardAtEntry,
+    /// No room has the wumpus in it, so the game can't be won.
+    MissingWumpus,
+    /// Every way to the wumpus's room leads through a pit or bats, so the
+    /// player can't walk close enough to shoot it. Holds the room's name.
+    LairBehindHazards(String),
+    /// A saved game was written by a version of the game that this one
+    /// can't read. Holds the version.
+    UnsupportedVersion(u64),
+}
+
+impl Problem {
+    pub fn new(path: impl Into<String>, kind: ProblemKind) -> Problem {
+        Problem { path: path.into(), kind }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.path)?;
+        match &self.kind {
+            ProblemKind::Missing => write!(f, "missing"),
+            ProblemKind::WrongType(expected) => write!(f, "expected {}", expected),
+            ProblemKind::DuplicateName(name, first) => {
+                write!(f, "room name {:?} is already used by $.rooms[{}]", name, first)
+            }
+            ProblemKind::HallLength(len) => write!(f, "a hall connects 2 rooms, not {}", len),
+            ProblemKind::NoSuchRoom(room) => write!(f, "there is no room {}", room),
+            ProblemKind::SelfLoop => write!(f, "a hall can't connect a room to itself"),
+            ProblemKind::Unreachable(name) => write!(f, "room {:?} can't be reached", name),
+            ProblemKind::TooManyCurios(n) => {
+                write!(f, "a room can have at most {} curios, not {}", MAX_CURIOS, n)
+            }
+            ProblemKind::HazardAtEntry => write!(f, "the first room can't have a pit or bats"),
+            ProblemKind::MissingWumpus => write!(f, "no room has the wumpus"),
+            ProblemKind::LairBehindHazards(name) => {
+                write!(f, "the wumpus in room {:?} can only be reached through a pit or bats", name)
+            }
+            ProblemKind::UnsupportedVersion(version) => {
+                write!(f, "saved by version {} of the game, which this one can't load", version)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Json(err) => write!(f, "not valid JSON: {}", err),
+            Error::Invalid(problems) => {
+                let problems: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+                write!(f, "{}", problems.join("\n"))
+            }
+        }
+    }
+}
 
 pub struct Board {
     pub rooms: Vec<Rc<RefCell<Room>>>,
+    /// The halls, in the order they were defined.
+    pub halls: Vec<Rc<Hall>>,
 }
 
 impl Board {
-    pub fn build_board(reader: &mut impl Read) -> Result<Board> {
-        let mut board = Board { rooms: Vec::new() };
+    /// Reads a board from JSON, and checks it. Every problem with it is
+    /// reported, not just the first. Curios are generated with `rng`, so a
+    /// seeded `rng` builds the same board each time.
+    pub fn build_board(reader: &mut impl Read, rng: &mut impl Rng) -> Result<Board> {
+        let board_json: Json = Json::from_reader(reader).map_err(Error::Json)?;
+        let mut problems = Vec::new();
+        if !board_json.is_object() {
+            problems.push(Problem::new("$", ProblemKind::WrongType("an object")));
+            return Err(Error::Invalid(problems));
+        }
 
-        let board_json: Json =
-            Json::from_reader(reader).map_err(|_| "Unable to create JSON reader".to_string())?;
+        let board = Board::from_json(&board_json, rng, &mut problems);
+        board.check_wumpus(&mut problems);
+        validated(board, problems)
+    }
 
+    /// Reads the rooms and halls of the object `json`, and checks that every
+    /// room can be reached, recording any problems. Curios that are counted
+    /// are generated with `rng`.
+    pub fn from_json(json: &Json, rng: &mut impl Rng, problems: &mut Vec<Problem>) -> Board {
+        let mut board = Board { rooms: Vec::new(), halls: Vec::new() };
+        board.parse_rooms(json, rng, problems);
+        board.parse_halls(json, problems);
+        board.check_reachable(problems);
         board
-            .parse_rooms(&board_json)
-            .map_err(|_| "Unable to parse rooms".to_string())?;
-        board
-            .parse_halls(&board_json)
-            .map_err(|_| "Unable to parse halls".to_string())?;
-
-        Ok(board)
-    }
-
-    fn parse_rooms(&mut self, json: &Json) -> Result<()> {
-        // Find room list
-        let rooms_opt: Option<&Json> = json.find("rooms");
-        let json_rooms: &Json = rooms_opt.ok_or("Unable to parse rooms".to_string())?;
-
-        // Parse each room
-        let rooms: &Vec<Json> = json_rooms
-            .as_array()
-            .ok_or("Unable to parse name".to_string())?;
-        for r in rooms {
-            // Parse room name
-            let name_opt: Option<&Json> = r.find("name");
-            let json_name: &Json = name_opt.ok_or("Unable to parse name".to_string())?;
-            let name: &str = json_name
-                .as_string()
-                .ok_or("Unable to parse name".to_string())?;
-
-            // Parse curios
-            let curios_opt: Option<&Json> = r.find("curios");
-            let json_curios: &Json = curios_opt.ok_or("Unable to parse curio".to_string())?;
-            let n: u64 = json_curios
-                .as_u64()
-                .ok_or("Unable to parse curio".to_string())?;
-            let curios: Vec<Curio> = Curio::generate_n(n as usize);
-
-            // Wumpus?
-            let wumpus: bool = {
-                if let Some(json_wumpus) = r.find("wumpus") {
-                    json_wumpus
-                        .as_boolean()
-                        .ok_or("Unable to parse Wumpus".to_string())?
+    }
+
+    fn parse_rooms(&mut self, json: &Json, rng: &mut impl Rng, problems: &mut Vec<Problem>) {
+        let Some(rooms) = array(json, "$", "rooms", problems) else { return };
+
+        // The first room with each name, ignoring case.
+        let mut names: HashMap<String, usize> = HashMap::new();
+        for (i, r) in rooms.iter().enumerate() {
+            let path = format!("$.rooms[{}]", i);
+            if !r.is_object() {
+                problems.push(Problem::new(path, ProblemKind::WrongType("an object")));
+                // Keep the room's place, so that the rooms after it keep
+                // their numbers.
+                self.rooms.push(Rc::new(RefCell::new(Room::new())));
+                continue;
+            }
+
+            let name = field(r, &path, "name", "a string", problems, Json::as_string);
+            if let Some(name) = name {
+                if let Some(&first) = names.get(&name.to_lowercase()) {
+                    let kind = ProblemKind::DuplicateName(name.to_string(), first);
+                    problems.push(Problem::new(format!("{}.name", path), kind));
                 } else {
-                    false
+                    names.insert(name.to_lowercase(), i);
                 }
+            }
+
+            // Curios are either listed, as in a saved game, or counted, to be
+            // generated.
+            let contents = match r.find("contents") {
+                Some(_) => field(r, &path, "contents", "an array", problems, Json::as_array)
+                    .map(|contents| parse_contents(contents, &path, problems)),
+                None => match field(r, &path, "curios", "a non-negative integer", problems, Json::as_u64) {
+                    Some(n) if n > MAX_CURIOS => {
+                        problems.push(Problem::new(format!("{}.curios", path), ProblemKind::TooManyCurios(n)));
+                        None
+                    }
+                    n => n.map(|n| Curio::generate_n(n as usize, rng)),
+                },
             };
 
-            // Add the new room to self.rooms
+            // Wumpus? Hazards?
+            let wumpus = flag(r, &path, "wumpus", problems);
+            let pit = flag(r, &path, "pit", problems);
+            let bats = flag(r, &path, "bats", problems);
+            if i == 0 && (pit || bats) {
+                problems.push(Problem::new(path.clone(), ProblemKind::HazardAtEntry));
+            }
+
+            // Add the new room to self.rooms, even if it's invalid.
             self.rooms.push(Rc::new(RefCell::new(Room {
-                name: name.to_string(),
-                contents: curios,
+                name: name.unwrap_or_default().to_string(),
+                contents: contents.unwrap_or_default(),
                 halls: vec![],
                 wumpus,
+                pit,
+                bats,
             })));
         }
-        Ok(())
     }
 
-    fn parse_halls(&mut self, json: &Json) -> Result<()> {
-        // Find hall list
-        let halls_opt: Option<&Json> = json.find("halls");
-        let json_halls: &Json = halls_opt.ok_or("Unable to parse halls".to_string())?;
+    fn parse_halls(&mut self, json: &Json, problems: &mut Vec<Problem>) {
+        let Some(halls) = array(json, "$", "halls", problems) else { return };
 
-        // Parse each hall
-        let halls: &Vec<Json> = json_halls
-            .as_array()
-            .ok_or("Unable to parse halls".to_string())?;
-        for h in halls {
-            let h: &Vec<Json> = h.as_array().ok_or("Unable to parse halls".to_string())?;
-            if h.len() > 2 {
-                return Err("Invalid number of rooms per hall".to_string());
+        for (i, h) in halls.iter().enumerate() {
+            let path = format!("$.halls[{}]", i);
+            let Some(h) = h.as_array() else {
+                problems.push(Problem::new(path, ProblemKind::WrongType("an array")));
+                continue;
+            };
+            if h.len() != 2 {
+                problems.push(Problem::new(path, ProblemKind::HallLength(h.len())));
+                continue;
             }
 
-            let (left, right) = (
-                h[0].as_u64().unwrap() as usize,
-                h[1].as_u64().unwrap() as usize,
-            );
+            let mut ends = vec![];
+            for (j, end) in h.iter().enumerate() {
+                let end_path = format!("{}[{}]", path, j);
+                match end.as_u64() {
+                    Some(room) if room as usize >= self.rooms.len() => {
+                        problems.push(Problem::new(end_path, ProblemKind::NoSuchRoom(room)));
+                    }
+                    Some(room) => ends.push(room as usize),
+                    None => {
+                        let kind = ProblemKind::WrongType("a room number");
+                        problems.push(Problem::new(end_path, kind));
+                    }
+                }
+            }
+            let [left, right] = ends[..] else { continue };
+            if left == right {
+                problems.push(Problem::new(path, ProblemKind::SelfLoop));
+                continue;
+            }
 
             // Add room links to halls
             let hall = Rc::new(Hall {
@@ -108,11 +251,306 @@ impl Board {
             // Add hall links to rooms
             self.rooms[left].borrow_mut().halls.push(hall.clone());
             self.rooms[right].borrow_mut().halls.push(hall.clone());
+            self.halls.push(hall);
         }
-        Ok(())
+    }
+
+    /// Checks that the player can reach every room from the first.
+    fn check_reachable(&self, problems: &mut Vec<Problem>) {
+        let reached = self.reached(|_| false);
+        for (i, room) in self.rooms.iter().enumerate() {
+            if !reached[i] {
+                let kind = ProblemKind::Unreachable(room.borrow().name.clone());
+                problems.push(Problem::new(format!("$.rooms[{}]", i), kind));
+            }
+        }
+    }
+
+    /// Checks that some room has the wumpus, and that the player can get
+    /// next to it without walking into a pit or bats, which carry the player
+    /// off elsewhere. Rooms that can't be reached at all are reported by
+    /// `from_json`.
+    pub fn check_wumpus(&self, problems: &mut Vec<Problem>) {
+        if !self.rooms.iter().any(|room| room.borrow().wumpus) {
+            problems.push(Problem::new("$.rooms", ProblemKind::MissingWumpus));
+        }
+        let reached = self.reached(|_| false);
+        let reached_safely = self.reached(|room| room.pit || room.bats);
+        for (i, room) in self.rooms.iter().enumerate() {
+            if room.borrow().wumpus && reached[i] && !reached_safely[i] {
+                let kind = ProblemKind::LairBehindHazards(room.borrow().name.clone());
+                problems.push(Problem::new(format!("$.rooms[{}]", i), kind));
+            }
+        }
+    }
+
+    /// Which rooms can be reached from the first room, without going on
+    /// from a room that `stops` is true for. Such rooms are reached, but the
+    /// rooms past them aren't.
+    fn reached(&self, stops: impl Fn(&Room) -> bool) -> Vec<bool> {
+        let mut reached = vec![false; self.rooms.len()];
+        let mut todo = vec![];
+        if !self.rooms.is_empty() {
+            reached[0] = true;
+            todo.push(0);
+        }
+        while let Some(i) = todo.pop() {
+            let room = self.rooms[i].borrow();
+            if i != 0 && stops(&room) {
+                continue;
+            }
+            for hall in &room.halls {
+                let other = hall.other(&room);
+                if let Some(j) = self.index_of(&other) {
+                    if !reached[j] {
+                        reached[j] = true;
+                        todo.push(j);
+                    }
+                }
+            }
+        }
+        reached
+    }
+
+    /// The number of `room`. Names may be duplicated, so the room is found
+    /// by identity.
+    pub fn index_of(&self, room: &Rc<RefCell<Room>>) -> Option<usize> {
+        self.rooms.iter().position(|r| Rc::ptr_eq(r, room))
     }
 
     pub fn spawn_location(&self) -> Rc<RefCell<Room>> {
         self.rooms[0].clone()
     }
 }
+
+impl ToJson for Board {
+    /// Writes the board in the form `build_board` reads, with each room's
+    /// curios listed rather than counted.
+    fn to_json(&self) -> Json {
+        let rooms = self.rooms.iter().map(|room| {
+            let room = room.borrow();
+            let mut object = BTreeMap::new();
+            object.insert("name".to_string(), room.name.to_json());
+            object.insert("contents".to_string(), room.contents.to_json());
+            object.insert("wumpus".to_string(), room.wumpus.to_json());
+            object.insert("pit".to_string(), room.pit.to_json());
+            object.insert("bats".to_string(), room.bats.to_json());
+            Json::Object(object)
+        });
+        let halls = self.halls.iter().map(|hall| {
+            let ends = [&hall.left, &hall.right].map(|room| self.index_of(room).expect("hall leaves the board"));
+            ends.to_vec().to_json()
+        });
+
+        let mut object = BTreeMap::new();
+        object.insert("rooms".to_string(), Json::Array(rooms.collect()));
+        object.insert("halls".to_string(), Json::Array(halls.collect()));
+        Json::Object(object)
+    }
+}
+
+/// Returns `value`, or fails with the problems found while reading it.
+pub fn validated<T>(value: T, problems: Vec<Problem>) -> Result<T> {
+    if problems.is_empty() {
+        Ok(value)
+    } else {
+        Err(Error::Invalid(problems))
+    }
+}
+
+/// Reads the curios listed in the room at `path`.
+fn parse_contents(contents: &[Json], path: &str, problems: &mut Vec<Problem>) -> Vec<Curio> {
+    let mut curios = vec![];
+    for (i, curio) in contents.iter().enumerate() {
+        match Curio::from_json(curio) {
+            Some(curio) => curios.push(curio),
+            None => {
+                let kind = ProblemKind::WrongType("a curio");
+                problems.push(Problem::new(format!("{}.contents[{}]", path, i), kind));
+            }
+        }
+    }
+    curios
+}
+
+/// Reads the array `key` of the object at `path`.
+fn array<'a>(json: &'a Json, path: &str, key: &str, problems: &mut Vec<Problem>) -> Option<&'a Vec<Json>> {
+    field(json, path, key, "an array", problems, Json::as_array)
+}
+
+/// Reads the boolean `key` of the object at `path`, which is false if it's
+/// missing.
+fn flag(json: &Json, path: &str, key: &str, problems: &mut Vec<Problem>) -> bool {
+    match json.find(key) {
+        Some(_) => field(json, path, key, "a boolean", problems, Json::as_boolean).unwrap_or(false),
+        None => false,
+    }
+}
+
+/// Reads `key` of the object at `path` with `read`, or records why it can't,
+/// with a description of the `expected` value.
+pub(super) fn field<'a, T>(
+    json: &'a Json,
+    path: &str,
+    key: &str,
+    expected: &'static str,
+    problems: &mut Vec<Problem>,
+    read: impl Fn(&'a Json) -> Option<T>,
+) -> Option<T> {
+    let path = format!("{}.{}", path, key);
+    let Some(value) = json.find(key) else {
+        problems.push(Problem::new(path, ProblemKind::Missing));
+        return None;
+    };
+    let value = read(value);
+    if value.is_none() {
+        problems.push(Problem::new(path, ProblemKind::WrongType(expected)));
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn build(reader: &mut impl Read, seed: u64) -> Result<Board> {
+        Board::build_board(reader, &mut StdRng::seed_from_u64(seed))
+    }
+
+    fn problems(src: &str) -> Vec<Problem> {
+        match build(&mut src.as_bytes(), 0) {
+            Err(Error::Invalid(problems)) => problems,
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("{} is valid", src),
+        }
+    }
+
+    fn problem(path: &str, kind: ProblemKind) -> Problem {
+        Problem::new(path, kind)
+    }
+
+    #[test]
+    fn test_castle() {
+        let mut file = std::fs::File::open("data/castle.json").unwrap();
+        let board = build(&mut file, 0).unwrap();
+        assert_eq!(board.rooms.len(), 16);
+        assert_eq!(board.spawn_location().borrow().name, "Entry");
+    }
+
+    #[test]
+    fn test_seed() {
+        let contents = |seed| {
+            let mut file = std::fs::File::open("data/castle.json").unwrap();
+            let board = build(&mut file, seed).unwrap();
+            let contents: Vec<Vec<Curio>> = board.rooms.iter().map(|room| room.borrow().contents.clone()).collect();
+            contents
+        };
+        assert_eq!(contents(7), contents(7));
+        assert_ne!(contents(7), contents(8));
+    }
+
+    #[test]
+    fn test_not_json() {
+        assert!(matches!(build(&mut "{ rooms".as_bytes(), 0), Err(Error::Json(_))));
+        assert_eq!(problems("[]"), vec![problem("$", ProblemKind::WrongType("an object"))]);
+    }
+
+    #[test]
+    fn test_types() {
+        let src = r#"{ "rooms": [
+            {"name": "Entry", "curios": -1},
+            {"name": 2, "curios": 0, "wumpus": "yes", "pit": 1},
+            {"curios": 0},
+            "Lair"
+        ], "halls": {} }"#;
+        assert_eq!(problems(src), vec![
+            problem("$.rooms[0].curios", ProblemKind::WrongType("a non-negative integer")),
+            problem("$.rooms[1].name", ProblemKind::WrongType("a string")),
+            problem("$.rooms[1].wumpus", ProblemKind::WrongType("a boolean")),
+            problem("$.rooms[1].pit", ProblemKind::WrongType("a boolean")),
+            problem("$.rooms[2].name", ProblemKind::Missing),
+            problem("$.rooms[3]", ProblemKind::WrongType("an object")),
+            problem("$.halls", ProblemKind::WrongType("an array")),
+            problem("$.rooms[1]", ProblemKind::Unreachable("".to_string())),
+            problem("$.rooms[2]", ProblemKind::Unreachable("".to_string())),
+            problem("$.rooms[3]", ProblemKind::Unreachable("".to_string())),
+            problem("$.rooms", ProblemKind::MissingWumpus),
+        ]);
+    }
+
+    #[test]
+    fn test_halls() {
+        let src = r#"{ "rooms": [
+            {"name": "Entry", "curios": 0},
+            {"name": "entry", "curios": 0},
+            {"name": "Lair", "curios": 0, "wumpus": true},
+            {"name": "Attic", "curios": 0}
+        ], "halls": [[0, 1], [1], [0, 1, 2], [1, 7], [2, 2], [0, "Lair"], 3] }"#;
+        assert_eq!(problems(src), vec![
+            problem("$.rooms[1].name", ProblemKind::DuplicateName("entry".to_string(), 0)),
+            problem("$.halls[1]", ProblemKind::HallLength(1)),
+            problem("$.halls[2]", ProblemKind::HallLength(3)),
+            problem("$.halls[3][1]", ProblemKind::NoSuchRoom(7)),
+            problem("$.halls[4]", ProblemKind::SelfLoop),
+            problem("$.halls[5][1]", ProblemKind::WrongType("a room number")),
+            problem("$.halls[6]", ProblemKind::WrongType("an array")),
+            problem("$.rooms[2]", ProblemKind::Unreachable("Lair".to_string())),
+            problem("$.rooms[3]", ProblemKind::Unreachable("Attic".to_string())),
+        ]);
+        assert_eq!(
+            problems(r#"{"rooms": []}"#),
+            vec![problem("$.halls", ProblemKind::Missing), problem("$.rooms", ProblemKind::MissingWumpus)]
+        );
+    }
+
+    #[test]
+    fn test_too_many_curios() {
+        let src = r#"{ "rooms": [
+            {"name": "Entry", "curios": 1000},
+            {"name": "Lair", "curios": 18446744073709551615, "wumpus": true}
+        ], "halls": [[0, 1]] }"#;
+        assert_eq!(problems(src), vec![
+            problem("$.rooms[1].curios", ProblemKind::TooManyCurios(u64::MAX)),
+        ]);
+    }
+
+    #[test]
+    fn test_hazards() {
+        let src = r#"{ "rooms": [
+            {"name": "Entry", "curios": 0, "bats": true},
+            {"name": "Pit", "curios": 0, "pit": true},
+            {"name": "Lair", "curios": 0, "wumpus": true},
+            {"name": "Roost", "curios": 0, "bats": true}
+        ], "halls": [[0, 1], [1, 2], [0, 3]] }"#;
+        assert_eq!(problems(src), vec![
+            problem("$.rooms[0]", ProblemKind::HazardAtEntry),
+            problem("$.rooms[2]", ProblemKind::LairBehindHazards("Lair".to_string())),
+        ]);
+
+        // Bats carry the player off, so they block the way too.
+        let src = r#"{ "rooms": [
+            {"name": "Entry", "curios": 0},
+            {"name": "Pit", "curios": 0, "pit": true},
+            {"name": "Lair", "curios": 0, "wumpus": true},
+            {"name": "Roost", "curios": 0, "bats": true},
+            {"name": "Hall", "curios": 0}
+        ], "halls": [[0, 1], [1, 2], [0, 3], [3, 2]] }"#;
+        assert_eq!(problems(src), vec![
+            problem("$.rooms[4]", ProblemKind::Unreachable("Hall".to_string())),
+            problem("$.rooms[2]", ProblemKind::LairBehindHazards("Lair".to_string())),
+        ]);
+
+        // The lair may be next to a pit and bats, as long as a safe room is.
+        let src = r#"{ "rooms": [
+            {"name": "Entry", "curios": 0},
+            {"name": "Pit", "curios": 0, "pit": true},
+            {"name": "Lair", "curios": 0, "wumpus": true},
+            {"name": "Roost", "curios": 0, "bats": true},
+            {"name": "Hall", "curios": 0}
+        ], "halls": [[0, 1], [1, 2], [0, 3], [3, 2], [0, 4], [4, 2]] }"#;
+        assert!(build(&mut src.as_bytes(), 0).is_ok());
+    }
+}
@@ -1,4 +1,7 @@
-use rand;
+use std::collections::BTreeMap;
+
+use rand::Rng;
+use rustc_serialize::json::{Json, ToJson};
 
 const MAX_CHEST_VAL: u32 = 100;
 const MAX_TRAP_VAL: u32 = 10;
@@ -6,7 +9,7 @@ const MAX_FOOD_VAL: u32 = 10;
 const NUM_CURIO_VARIANTS: usize = 5;
 const NUM_NONRECURSIVE_CURIOS: usize = 3;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Curio {
     Chest(i32),
     SpikeTrap(i32),
@@ -16,46 +19,92 @@ pub enum Curio {
 }
 
 impl Curio {
-    pub fn generate_n(n: usize) -> Vec<Curio> {
+    pub fn generate_n(n: usize, rng: &mut impl Rng) -> Vec<Curio> {
         let mut acc = Vec::new();
         for _ in 0..n {
-            acc.push(Curio::generate());
+            acc.push(Curio::generate(rng));
         }
         acc
     }
 
-    pub fn generate() -> Curio {
-        match rand::random::<usize>() % NUM_CURIO_VARIANTS {
-            0 => Curio::rand_chest(),
-            1 => Curio::rand_spike_trap(),
-            2 => Curio::rand_food(),
+    pub fn generate(rng: &mut impl Rng) -> Curio {
+        match rng.gen::<usize>() % NUM_CURIO_VARIANTS {
+            0 => Curio::rand_chest(rng),
+            1 => Curio::rand_spike_trap(rng),
+            2 => Curio::rand_food(rng),
             3 => Curio::IronMaiden(
-                Box::new(Curio::generate_sub_curio()),
-                ((rand::random::<u32>() % MAX_TRAP_VAL) + 1) as i32,
+                Box::new(Curio::generate_sub_curio(rng)),
+                ((rng.gen::<u32>() % MAX_TRAP_VAL) + 1) as i32,
             ),
-            4 => Curio::FallenAdventurer(Box::new(Curio::generate_sub_curio())),
+            4 => Curio::FallenAdventurer(Box::new(Curio::generate_sub_curio(rng))),
             _ => unreachable!(),
         }
     }
 
-    fn rand_chest() -> Curio {
-        Curio::Chest(((rand::random::<u32>() % MAX_CHEST_VAL) + 1) as i32)
+    fn rand_chest(rng: &mut impl Rng) -> Curio {
+        Curio::Chest(((rng.gen::<u32>() % MAX_CHEST_VAL) + 1) as i32)
     }
 
-    fn rand_spike_trap() -> Curio {
-        Curio::SpikeTrap(((rand::random::<u32>() % MAX_TRAP_VAL) + 1) as i32)
+    fn rand_spike_trap(rng: &mut impl Rng) -> Curio {
+        Curio::SpikeTrap(((rng.gen::<u32>() % MAX_TRAP_VAL) + 1) as i32)
     }
 
-    fn rand_food() -> Curio {
-        Curio::Food(((rand::random::<u32>() % MAX_FOOD_VAL) + 1) as i32)
+    fn rand_food(rng: &mut impl Rng) -> Curio {
+        Curio::Food(((rng.gen::<u32>() % MAX_FOOD_VAL) + 1) as i32)
     }
 
-    fn generate_sub_curio() -> Curio {
-        match rand::random::<usize>() % NUM_NONRECURSIVE_CURIOS {
-            0 => Curio::rand_chest(),
-            1 => Curio::rand_spike_trap(),
-            2 => Curio::rand_food(),
+    fn generate_sub_curio(rng: &mut impl Rng) -> Curio {
+        match rng.gen::<usize>() % NUM_NONRECURSIVE_CURIOS {
+            0 => Curio::rand_chest(rng),
+            1 => Curio::rand_spike_trap(rng),
+            2 => Curio::rand_food(rng),
             _ => unreachable!(),
         }
     }
 }
+
+impl Curio {
+    /// Reads a curio written by `to_json`.
+    pub fn from_json(json: &Json) -> Option<Curio> {
+        let object = json.as_object()?;
+        if object.len() != 1 {
+            return None;
+        }
+        let (kind, value) = object.iter().next()?;
+        let int = |json: &Json| json.as_i64().and_then(|x| i32::try_from(x).ok());
+        match kind.as_str() {
+            "chest" => int(value).map(Curio::Chest),
+            "spike_trap" => int(value).map(Curio::SpikeTrap),
+            "food" => int(value).map(Curio::Food),
+            "iron_maiden" => {
+                let sub = Curio::from_json(value.find("contents")?)?;
+                let damage = int(value.find("damage")?)?;
+                Some(Curio::IronMaiden(Box::new(sub), damage))
+            }
+            "fallen_adventurer" => Curio::from_json(value).map(|sub| Curio::FallenAdventurer(Box::new(sub))),
+            _ => None,
+        }
+    }
+}
+
+impl ToJson for Curio {
+    /// Writes the curio as an object with one key, its kind, like
+    /// `{"chest": 40}`, so that the curios inside it are kept.
+    fn to_json(&self) -> Json {
+        let (kind, value) = match self {
+            Curio::Chest(gold) => ("chest", gold.to_json()),
+            Curio::SpikeTrap(dmg) => ("spike_trap", dmg.to_json()),
+            Curio::Food(heal) => ("food", heal.to_json()),
+            Curio::IronMaiden(sub, dmg) => {
+                let mut object = BTreeMap::new();
+                object.insert("contents".to_string(), sub.to_json());
+                object.insert("damage".to_string(), dmg.to_json());
+                ("iron_maiden", Json::Object(object))
+            }
+            Curio::FallenAdventurer(sub) => ("fallen_adventurer", sub.to_json()),
+        };
+        let mut object = BTreeMap::new();
+        object.insert(kind.to_string(), value);
+        Json::Object(object)
+    }
+}
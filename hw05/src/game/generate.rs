@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rustc_serialize::json::{Json, ToJson};
+
+use super::board::MAX_CURIOS;
+
+/// The most rooms a generated castle can have.
+pub const MAX_ROOMS: usize = 1000;
+
+/// Names for generated rooms. When they run out, they're reused with numbers.
+const ROOM_NAMES: [&str; 24] = [
+    "Foyer", "Library", "Chamber", "Kitchen", "Pantry", "Dungeon", "Lair", "Treasury",
+    "Sewer", "Armory", "Chapel", "Crypt", "Gallery", "Cellar", "Vault", "Tower",
+    "Study", "Barracks", "Garden", "Well", "Ossuary", "Forge", "Kennel", "Observatory",
+];
+
+/// Where the wumpus goes in a generated castle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wumpus {
+    /// In a room as many halls from the entry as any room is.
+    Far,
+    /// In any room but the entry.
+    Random,
+    /// In the room with this number, which can't be the entry, 0.
+    Room(usize),
+}
+
+/// The shape of a generated castle.
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    /// How many rooms there are, counting the entry.
+    pub rooms: usize,
+    /// How many halls lead from a room, on average. Every room can be reached
+    /// however low this is; higher values add halls that make loops.
+    pub branching: f64,
+    /// How many curios are in a room, on average. The entry has none. At
+    /// most half of `MAX_CURIOS`, since rooms get up to twice this many.
+    pub curios: f64,
+    pub wumpus: Wumpus,
+}
+
+impl Default for Options {
+    /// A castle the size of `data/castle.json`.
+    fn default() -> Self {
+        Options { rooms: 16, branching: 2.6, curios: 2.5, wumpus: Wumpus::Far }
+    }
+}
+
+/// Generates a castle, in the JSON that `Board::build_board` reads. The first
+/// room is the entry, where the player starts, and every room, including the
+/// wumpus's, can be reached from it.
+pub fn generate(options: &Options, rng: &mut impl Rng) -> Result<Json, String> {
+    let n = options.rooms;
+    if n < 2 {
+        return Err("a castle needs at least 2 rooms".to_string());
+    }
+    if n > MAX_ROOMS {
+        return Err(format!("a castle can have at most {} rooms", MAX_ROOMS));
+    }
+    let finite = |x: f64| x.is_finite() && x >= 0.0;
+    if !(finite(options.branching) && finite(options.curios)) {
+        return Err("branching and curios must be non-negative numbers".to_string());
+    }
+    let most_curios = (options.curios * 2.0).round() as u64;
+    if most_curios > MAX_CURIOS {
+        return Err(format!("curios can be at most {}", MAX_CURIOS / 2));
+    }
+
+    // Join each room to one before it, so that they're all connected, then
+    // add halls between random rooms until there are enough.
+    let mut halls: Vec<(usize, usize)> = (1..n).map(|i| (rng.gen_range(0..i), i)).collect();
+    let pairs = n * (n - 1) / 2;
+    let wanted = ((n as f64 * options.branching / 2.0).round() as usize).min(pairs);
+    let mut joined: HashSet<(usize, usize)> = halls.iter().copied().collect();
+    if wanted * 2 > pairs {
+        // Most pairs get a hall, so listing them all costs no more than the
+        // halls themselves, and drawing at random would keep hitting joined
+        // pairs.
+        let mut unjoined: Vec<(usize, usize)> = (0..n)
+            .flat_map(|b| (0..b).map(move |a| (a, b)))
+            .filter(|pair| !joined.contains(pair))
+            .collect();
+        unjoined.shuffle(rng);
+        halls.extend(unjoined.into_iter().take(wanted - halls.len()));
+    } else {
+        while halls.len() < wanted {
+            let a = rng.gen_range(0..n);
+            let b = rng.gen_range(0..n);
+            let pair = (a.min(b), a.max(b));
+            if a != b && joined.insert(pair) {
+                halls.push(pair);
+            }
+        }
+    }
+    // Otherwise the room numbers give away which halls came first.
+    halls.shuffle(rng);
+
+    let wumpus = match options.wumpus {
+        Wumpus::Far => {
+            let distances = distances(n, &halls);
+            let farthest = *distances.iter().max().unwrap();
+            let far: Vec<usize> = (0..n).filter(|&i| distances[i] == farthest).collect();
+            *far.choose(rng).unwrap()
+        }
+        Wumpus::Random => rng.gen_range(1..n),
+        Wumpus::Room(0) => return Err("the wumpus can't be in the entry".to_string()),
+        Wumpus::Room(i) if i < n => i,
+        Wumpus::Room(i) => return Err(format!("there is no room {} for the wumpus", i)),
+    };
+
+    let mut names: Vec<String> = vec![];
+    let mut pool: Vec<&str> = ROOM_NAMES.to_vec();
+    pool.shuffle(rng);
+    for i in 1..n {
+        let name = pool[(i - 1) % pool.len()];
+        let round = (i - 1) / pool.len();
+        names.push(if round == 0 { name.to_string() } else { format!("{}{}", name, round + 1) });
+    }
+
+    let rooms = (0..n).map(|i| {
+        let mut room = BTreeMap::new();
+        let (name, curios) = match i {
+            0 => ("Entry".to_string(), 0),
+            i => (names[i - 1].clone(), rng.gen_range(0..=most_curios)),
+        };
+        room.insert("name".to_string(), name.to_json());
+        room.insert("curios".to_string(), curios.to_json());
+        if i == wumpus {
+            room.insert("wumpus".to_string(), true.to_json());
+        }
+        Json::Object(room)
+    });
+
+    let mut castle = BTreeMap::new();
+    castle.insert("rooms".to_string(), Json::Array(rooms.collect()));
+    let halls = halls.iter().map(|&(a, b)| vec![a, b].to_json());
+    castle.insert("halls".to_string(), Json::Array(halls.collect()));
+    Ok(Json::Object(castle))
+}
+
+/// How many halls each of `n` rooms is from the first.
+fn distances(n: usize, halls: &[(usize, usize)]) -> Vec<usize> {
+    let mut neighbors = vec![vec![]; n];
+    for &(a, b) in halls {
+        neighbors[a].push(b);
+        neighbors[b].push(a);
+    }
+    let mut distances = vec![usize::MAX; n];
+    let mut todo = VecDeque::from([0]);
+    distances[0] = 0;
+    while let Some(i) = todo.pop_front() {
+        for &other in &neighbors[i] {
+            if distances[other] == usize::MAX {
+                distances[other] = distances[i] + 1;
+                todo.push_back(other);
+            }
+        }
+    }
+    distances
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::game::board::Board;
+
+    fn build(options: &Options, seed: u64) -> Board {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let castle = generate(options, &mut rng).unwrap().to_string();
+        Board::build_board(&mut castle.as_bytes(), &mut rng).unwrap()
+    }
+
+    #[test]
+    fn test_valid() {
+        // Building the board checks that every room can be reached, and that
+        // the wumpus is somewhere.
+        for seed in 0..50 {
+            for rooms in [2, 3, 10, 40] {
+                for branching in [0.0, 2.0, 4.0, 100.0] {
+                    let wumpus = [Wumpus::Far, Wumpus::Random, Wumpus::Room(1)][seed as usize % 3];
+                    let options = Options { rooms, branching, curios: 2.0, wumpus };
+                    let board = build(&options, seed);
+                    assert_eq!(board.rooms.len(), rooms);
+                    assert!(!board.spawn_location().borrow().wumpus);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_options() {
+        let options = Options { rooms: 10, branching: 3.0, curios: 0.0, wumpus: Wumpus::Room(4) };
+        let board = build(&options, 1);
+        assert_eq!(board.halls.len(), 15);
+        assert!(board.rooms[4].borrow().wumpus);
+        assert!(board.rooms.iter().all(|room| room.borrow().contents.is_empty()));
+
+        // A tree has no loops, so the wumpus is at the end of a branch.
+        let options = Options { branching: 0.0, wumpus: Wumpus::Far, ..options };
+        let board = build(&options, 1);
+        assert_eq!(board.halls.len(), 9);
+        let lair = board.rooms.iter().find(|room| room.borrow().wumpus).unwrap();
+        assert_eq!(lair.borrow().halls.len(), 1);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(generate(&Options { rooms: 1, ..options }, &mut rng).is_err());
+        assert!(generate(&Options { wumpus: Wumpus::Room(10), ..options }, &mut rng).is_err());
+        assert!(generate(&Options { wumpus: Wumpus::Room(0), ..options }, &mut rng).is_err());
+        assert!(generate(&Options { rooms: MAX_ROOMS + 1, ..options }, &mut rng).is_err());
+        assert!(generate(&Options { curios: f64::INFINITY, ..options }, &mut rng).is_err());
+        assert!(generate(&Options { curios: f64::NAN, ..options }, &mut rng).is_err());
+        assert!(generate(&Options { curios: 1000.0, ..options }, &mut rng).is_err());
+        assert!(generate(&Options { branching: f64::INFINITY, ..options }, &mut rng).is_err());
+        assert!(generate(&Options { branching: -1.0, ..options }, &mut rng).is_err());
+    }
+
+    #[test]
+    fn test_large() {
+        // Few extra halls are drawn at random; many are picked from every pair.
+        for branching in [3.0, 1e9] {
+            let options = Options { rooms: MAX_ROOMS, branching, curios: 500.0, wumpus: Wumpus::Far };
+            let mut rng = StdRng::seed_from_u64(0);
+            let castle = generate(&options, &mut rng).unwrap();
+            let halls = castle.find("halls").and_then(Json::as_array).unwrap();
+            let expected = if branching == 3.0 { 1500 } else { MAX_ROOMS * (MAX_ROOMS - 1) / 2 };
+            assert_eq!(halls.len(), expected);
+        }
+    }
+}
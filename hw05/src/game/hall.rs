@@ -8,6 +8,12 @@ pub struct Hall {
     pub right: Rc<RefCell<Room>>,
 }
 
+impl Default for Hall {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Hall {
     pub fn new() -> Hall {
         // TODO: Implement
@@ -1,5 +1,7 @@
 pub mod board;
 pub mod curio;
+pub mod generate;
 pub mod hall;
 pub mod player;
 pub mod room;
+pub mod save;
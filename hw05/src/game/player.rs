@@ -2,6 +2,9 @@ use std::cell::RefCell;
 use std::fmt;
 use std::rc::Rc;
 
+use rand::seq::SliceRandom;
+use rand::Rng;
+
 use super::curio::Curio;
 use super::room::Room;
 
@@ -16,13 +19,13 @@ pub struct Player {
     pub location: Rc<RefCell<Room>>,
     pub hp: i32,
     pub gold: i32,
-    won: bool,
+    pub won: bool,
 }
 
 impl Player {
     pub fn new(location: Rc<RefCell<Room>>) -> Player {
         Player {
-            location: location,
+            location,
             hp: MAX_HP,
             gold: 0,
             won: false,
@@ -60,16 +63,14 @@ impl Player {
         }
     }
 
-    /// Execute the given command on the player and board state.
-    pub fn act(&mut self, cmd: Command) -> Result<(), ()> {
+    /// Execute the given command on the player and board state. Fails with
+    /// the name of the room if it isn't a neighbor. Bats use `rng` to pick
+    /// where they drop the player.
+    pub fn act(&mut self, cmd: Command, rng: &mut impl Rng) -> Result<(), String> {
         match cmd {
             Command::Go(room) => {
                 let dst_room = self.find_room(room)?;
-                while let Some(curio) = dst_room.borrow_mut().consume_content() {
-                    self.use_curio(curio)
-                }
-
-                self.location = dst_room;
+                self.enter(dst_room, rng);
             }
             Command::Shoot(room) => {
                 let dst_room = self.find_room(room)?;
@@ -85,27 +86,152 @@ impl Player {
         Ok(())
     }
 
+    /// Move into `room` and suffer whatever is there: a pit kills the player,
+    /// bats carry them off to a random room without bats, and otherwise they
+    /// use up the room's curios.
+    fn enter(&mut self, room: Rc<RefCell<Room>>, rng: &mut impl Rng) {
+        self.location = room.clone();
+        if room.borrow().pit {
+            println!("You fall into a bottomless pit. You're still falling.");
+            self.hp = 0;
+            return;
+        }
+        if room.borrow().bats {
+            let perches: Vec<_> = castle(&room).into_iter().filter(|r| !r.borrow().bats).collect();
+            if let Some(perch) = perches.choose(rng) {
+                println!("Super bats snatch you up and drop you in {}!", perch.borrow().name);
+                self.enter(perch.clone(), rng);
+                return;
+            }
+        }
+        while let Some(curio) = room.borrow_mut().consume_content() {
+            self.use_curio(curio)
+        }
+    }
+
     /// Find one of the neighbors of the current room based on its name. Case insensitive.
-    fn find_room(&self, room: String) -> Result<Rc<RefCell<Room>>, ()> {
-        let room = room.to_lowercase();
+    fn find_room(&self, room: String) -> Result<Rc<RefCell<Room>>, String> {
+        let name = room.to_lowercase();
         for hall in self.location.borrow().halls.iter() {
             let other_side = hall.other(&self.location.borrow());
-            if other_side.borrow().name.to_lowercase() == room {
+            if other_side.borrow().name.to_lowercase() == name {
                 return Ok(other_side);
             }
         }
-        Err(())
+        Err(room)
     }
 }
 
+/// Every room that can be reached from `start`, including `start`.
+fn castle(start: &Rc<RefCell<Room>>) -> Vec<Rc<RefCell<Room>>> {
+    let mut rooms = vec![start.clone()];
+    let mut i = 0;
+    while i < rooms.len() {
+        let room = rooms[i].clone();
+        for hall in room.borrow().halls.iter() {
+            let other = hall.other(&room.borrow());
+            if !rooms.iter().any(|r| Rc::ptr_eq(r, &other)) {
+                rooms.push(other);
+            }
+        }
+        i += 1;
+    }
+    rooms
+}
+
 impl fmt::Display for Player {
+    /// Where the player is, what they can sense from the neighboring rooms,
+    /// and how they're doing.
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(
-            f,
-            "You find yourself in {}.\n\nYou have {} HP and {} gold.",
-            self.location.borrow().name,
-            self.hp,
-            self.gold
-        )
+        let room = self.location.borrow();
+        write!(f, "You find yourself in {}.\n\n", room.name)?;
+
+        let neighbors: Vec<_> = room.halls.iter().map(|hall| hall.other(&room)).collect();
+        let near = |hazard: fn(&Room) -> bool| neighbors.iter().any(|r| hazard(&r.borrow()));
+        if near(|r| r.wumpus) {
+            writeln!(f, "You smell a wumpus.")?;
+        }
+        if near(|r| r.pit) {
+            writeln!(f, "You feel a breeze.")?;
+        }
+        if near(|r| r.bats) {
+            writeln!(f, "You hear flapping.")?;
+        }
+        if near(|r| r.wumpus || r.pit || r.bats) {
+            writeln!(f)?;
+        }
+
+        write!(f, "You have {} HP and {} gold.", self.hp, self.gold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::game::board::Board;
+
+    fn build(castle: &str) -> Board {
+        Board::build_board(&mut castle.as_bytes(), &mut StdRng::seed_from_u64(0)).unwrap()
+    }
+
+    #[test]
+    fn test_senses() {
+        let board = build(r#"{
+            "rooms": [
+                {"name": "Entry", "curios": 0},
+                {"name": "Lair", "curios": 0, "wumpus": true},
+                {"name": "Well", "curios": 0, "pit": true},
+                {"name": "Belfry", "curios": 0, "bats": true}
+            ],
+            "halls": [[0, 1], [0, 2], [2, 3]]
+        }"#);
+        let player = Player::new(board.spawn_location());
+        assert_eq!(
+            player.to_string(),
+            "You find yourself in Entry.\n\nYou smell a wumpus.\nYou feel a breeze.\n\n\
+             You have 25 HP and 0 gold."
+        );
+        let player = Player::new(board.rooms[1].clone());
+        assert_eq!(player.to_string(), "You find yourself in Lair.\n\nYou have 25 HP and 0 gold.");
+    }
+
+    #[test]
+    fn test_hazards() {
+        let board = build(r#"{
+            "rooms": [
+                {"name": "Entry", "curios": 0},
+                {"name": "Well", "curios": 0, "pit": true},
+                {"name": "Belfry", "curios": 0, "bats": true},
+                {"name": "Lair", "contents": [{"chest": 7}], "wumpus": true}
+            ],
+            "halls": [[0, 1], [0, 2], [2, 3], [0, 3]]
+        }"#);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let mut player = Player::new(board.spawn_location());
+        player.act(Command::Go("well".to_string()), &mut rng).unwrap();
+        assert_eq!(player.hp, 0);
+
+        // Wherever the bats drop the player, the room works as if they had
+        // walked in.
+        let mut player = Player::new(board.rooms[0].clone());
+        while player.gold == 0 {
+            player.location = board.rooms[0].clone();
+            player.hp = MAX_HP;
+            player.act(Command::Go("belfry".to_string()), &mut rng).unwrap();
+        }
+        assert!(Rc::ptr_eq(&player.location, &board.rooms[3]));
+        assert_eq!(player.gold, 7);
+
+        // The bats drop the player in a room without bats, which can be where
+        // they started.
+        for _ in 0..10 {
+            let mut player = Player::new(board.spawn_location());
+            player.act(Command::Go("belfry".to_string()), &mut rng).unwrap();
+            assert!(!player.location.borrow().bats);
+        }
     }
 }
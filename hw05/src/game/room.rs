@@ -8,6 +8,10 @@ pub struct Room {
     pub contents: Vec<Curio>,
     pub halls: Vec<Rc<Hall>>,
     pub wumpus: bool,
+    /// A bottomless pit, which kills the player who enters.
+    pub pit: bool,
+    /// Super bats, which carry the player who enters to another room.
+    pub bats: bool,
 }
 
 impl PartialEq for Room {
@@ -18,6 +22,12 @@ impl PartialEq for Room {
 
 impl Eq for Room {}
 
+impl Default for Room {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Room {
     // TODO: Implement the necessary methods for Rooms.
     pub fn new() -> Self {
@@ -26,6 +36,8 @@ impl Room {
             contents: Vec::new(),
             halls: Vec::new(),
             wumpus: false,
+            pit: false,
+            bats: false,
         }
     }
 
@@ -34,15 +46,10 @@ impl Room {
     }
 
     pub fn neighbors_string(&self) -> String {
-        let description = format!(
-            "{}",
-            self.halls
-                .iter()
-                .map(|hall| hall.other(&self).borrow().name.clone())
-                .collect::<Vec<_>>()
-                .join(", "),
-        );
-
-        description
+        self.halls
+            .iter()
+            .map(|hall| hall.other(self).borrow().name.clone())
+            .collect::<Vec<_>>()
+            .join(", ")
     }
 }
@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use rand::Rng;
+use rustc_serialize::json::{Json, ToJson};
+
+use super::board::{self, field, Board, Error, Problem, ProblemKind};
+use super::player::Player;
+
+/// The version of the save format. Bump it when the format changes, so that
+/// older saves are refused rather than misread.
+pub const VERSION: u64 = 1;
+
+/// Writes the whole game to `writer`: the rooms, with the curios left in
+/// them and whether the wumpus is still there, the halls between them, and
+/// the player.
+pub fn save(writer: &mut impl Write, board: &Board, player: &Player) -> io::Result<()> {
+    let Json::Object(mut game) = board.to_json() else { unreachable!("a board is an object") };
+    let room = board.index_of(&player.location).expect("the player is off the board");
+
+    let mut json_player = BTreeMap::new();
+    json_player.insert("room".to_string(), room.to_json());
+    json_player.insert("hp".to_string(), player.hp.to_json());
+    json_player.insert("gold".to_string(), player.gold.to_json());
+    json_player.insert("won".to_string(), player.won.to_json());
+
+    game.insert("version".to_string(), VERSION.to_json());
+    game.insert("player".to_string(), Json::Object(json_player));
+    writeln!(writer, "{}", Json::Object(game).pretty())
+}
+
+/// Reads a game written by `save`, and rebuilds the rooms and the halls
+/// between them. The game is checked as a board is, and every problem with
+/// it is reported. Any curios that are counted rather than listed are
+/// generated with `rng`.
+pub fn load(reader: &mut impl Read, rng: &mut impl Rng) -> board::Result<(Board, Player)> {
+    let json: Json = Json::from_reader(reader).map_err(Error::Json)?;
+    let mut problems = Vec::new();
+    if !json.is_object() {
+        problems.push(Problem::new("$", ProblemKind::WrongType("an object")));
+        return Err(Error::Invalid(problems));
+    }
+
+    // The rest of a save from another version can't be read, so stop here.
+    field(&json, "$", "version", "a version number", &mut problems, Json::as_u64);
+    match json.find("version").and_then(Json::as_u64) {
+        Some(VERSION) => {}
+        Some(version) => problems.push(Problem::new("$.version", ProblemKind::UnsupportedVersion(version))),
+        None => {}
+    }
+    if !problems.is_empty() {
+        return Err(Error::Invalid(problems));
+    }
+
+    let board = Board::from_json(&json, rng, &mut problems);
+    let player = parse_player(&json, &board, &mut problems);
+    if let Some(player) = &player {
+        if !player.won {
+            board.check_wumpus(&mut problems);
+        }
+    }
+    match player {
+        Some(player) => board::validated((board, player), problems),
+        None => Err(Error::Invalid(problems)),
+    }
+}
+
+fn parse_player(json: &Json, board: &Board, problems: &mut Vec<Problem>) -> Option<Player> {
+    let player = field(json, "$", "player", "an object", problems, |p| p.as_object().map(|_| p))?;
+    let path = "$.player";
+    let int = |json: &Json| json.as_i64().and_then(|x| i32::try_from(x).ok());
+
+    let room = field(player, path, "room", "a room number", problems, Json::as_u64);
+    let hp = field(player, path, "hp", "an integer", problems, int);
+    let gold = field(player, path, "gold", "an integer", problems, int);
+    let won = field(player, path, "won", "a boolean", problems, Json::as_boolean);
+
+    let location = match room {
+        Some(room) if room as usize >= board.rooms.len() => {
+            problems.push(Problem::new("$.player.room", ProblemKind::NoSuchRoom(room)));
+            None
+        }
+        room => room.map(|room| board.rooms[room as usize].clone()),
+    };
+
+    let mut player = Player::new(location?);
+    player.hp = hp?;
+    player.gold = gold?;
+    player.won = won?;
+    Some(player)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::game::player::Command;
+
+    fn round_trip(board: &Board, player: &Player) -> (Board, Player) {
+        let mut out = vec![];
+        save(&mut out, board, player).unwrap();
+        load(&mut out.as_slice(), &mut StdRng::seed_from_u64(0)).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut file = std::fs::File::open("data/castle.json").unwrap();
+        let board = Board::build_board(&mut file, &mut StdRng::seed_from_u64(0)).unwrap();
+        board.rooms[2].borrow_mut().pit = true;
+        board.rooms[4].borrow_mut().bats = true;
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut player = Player::new(board.spawn_location());
+        player.act(Command::Go("foyer".to_string()), &mut rng).unwrap();
+        player.act(Command::Go("dungeon".to_string()), &mut rng).unwrap();
+
+        let (loaded, loaded_player) = round_trip(&board, &player);
+        for (room, loaded) in board.rooms.iter().zip(&loaded.rooms) {
+            let (room, loaded) = (room.borrow(), loaded.borrow());
+            assert_eq!(room.name, loaded.name);
+            assert_eq!(room.contents, loaded.contents);
+            assert_eq!((room.wumpus, room.pit, room.bats), (loaded.wumpus, loaded.pit, loaded.bats));
+            assert_eq!(room.neighbors_string(), loaded.neighbors_string());
+        }
+        assert_eq!(loaded_player.location.borrow().name, "Dungeon");
+        assert_eq!((loaded_player.hp, loaded_player.gold), (player.hp, player.gold));
+        // The rooms are shared by the halls again, so the player can move on.
+        assert!(loaded.rooms[6].borrow().contents.is_empty());
+        let mut loaded_player = loaded_player;
+        loaded_player.act(Command::Go("lair".to_string()), &mut rng).unwrap();
+        assert!(Rc::ptr_eq(&loaded_player.location, &loaded.rooms[7]));
+    }
+
+    #[test]
+    fn test_won_game_has_no_wumpus() {
+        let src = r#"{"rooms": [{"name": "Lair", "curios": 0, "wumpus": true}], "halls": []}"#;
+        let board = Board::build_board(&mut src.as_bytes(), &mut StdRng::seed_from_u64(0)).unwrap();
+        let mut player = Player::new(board.spawn_location());
+        board.rooms[0].borrow_mut().wumpus = false;
+        player.won = true;
+        let (_, loaded_player) = round_trip(&board, &player);
+        assert!(loaded_player.won);
+    }
+
+    #[test]
+    fn test_bad_saves() {
+        let problems = |src: &str| match load(&mut src.as_bytes(), &mut StdRng::seed_from_u64(0)) {
+            Err(Error::Invalid(problems)) => problems,
+            _ => panic!("{} loaded", src),
+        };
+        assert_eq!(
+            problems(r#"{"version": 2, "rooms": []}"#),
+            vec![Problem::new("$.version", ProblemKind::UnsupportedVersion(2))]
+        );
+        assert_eq!(problems(r#"{"rooms": []}"#), vec![Problem::new("$.version", ProblemKind::Missing)]);
+        let src = r#"{"version": 1, "halls": [],
+            "rooms": [{"name": "Lair", "contents": [{"chest": 5}, {"mimic": 3}], "wumpus": true}],
+            "player": {"room": 1, "hp": 25, "gold": "lots", "won": false}}"#;
+        assert_eq!(problems(src), vec![
+            Problem::new("$.rooms[0].contents[1]", ProblemKind::WrongType("a curio")),
+            Problem::new("$.player.gold", ProblemKind::WrongType("an integer")),
+            Problem::new("$.player.room", ProblemKind::NoSuchRoom(1)),
+        ]);
+    }
+}
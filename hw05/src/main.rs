@@ -6,19 +6,88 @@ extern crate rustc_serialize;
 
 use std::env::args;
 use std::fs::File;
+use std::process;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
 use game::board::Board;
+use game::generate::{self, Options, Wumpus};
 use game::player::Player;
 
+const USAGE: &str = "Usage: ./main [--seed N] file_name.json | cargo run -- [--seed N] file_name.json
+       cargo run -- [--seed N] generate [--rooms N] [--branching X] [--curios X] [--wumpus far|random|N]";
+
 fn main() {
-    let mut args = args();
-    if let Some(filename) = args.nth(1) {
+    let mut args: Vec<String> = args().skip(1).collect();
+
+    // Everything random in the game comes from the seed, so a game can be
+    // played again by passing its seed.
+    let seed = match args.iter().position(|arg| arg == "--seed") {
+        Some(i) => match args.get(i + 1).and_then(|seed| seed.parse().ok()) {
+            Some(seed) => {
+                args.drain(i..i + 2);
+                seed
+            }
+            None => {
+                eprintln!("--seed needs a number\n{}", USAGE);
+                process::exit(2);
+            }
+        },
+        None => rand::random(),
+    };
+
+    if args.first().map(String::as_str) == Some("generate") {
+        // The castle is written to stdout, so the seed goes to stderr.
+        eprintln!("Seed: {}", seed);
+        let res = parse_options(&args[1..])
+            .and_then(|options| generate::generate(&options, &mut StdRng::seed_from_u64(seed)));
+        match res {
+            Ok(castle) => println!("{}", castle.pretty()),
+            Err(err) => {
+                eprintln!("{}\n{}", err, USAGE);
+                process::exit(2);
+            }
+        }
+    } else if let Some(filename) = args.first() {
+        println!("Seed: {}", seed);
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut file_handle =
-            File::open(&filename).expect(&format!("Unable to open file {}", filename));
-        let board = Board::build_board(&mut file_handle).unwrap();
+            File::open(filename).unwrap_or_else(|_| panic!("Unable to open file {}", filename));
+        let board = match Board::build_board(&mut file_handle, &mut rng) {
+            Ok(board) => board,
+            Err(err) => {
+                eprintln!("Unable to load {}:\n{}", filename, err);
+                process::exit(1);
+            }
+        };
         let player = Player::new(board.spawn_location());
-        ui::game_loop(player);
+        ui::game_loop(board, player, rng);
     } else {
-        println!("Usage: ./main file_name.json | cargo run -- file_name.json");
+        println!("{}", USAGE);
+    }
+}
+
+/// Reads the options of the `generate` command.
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(format!("{} needs a value", flag))?;
+        let bad_value = || format!("{} can't be {}", flag, value);
+        match flag.as_str() {
+            "--rooms" => options.rooms = value.parse().map_err(|_| bad_value())?,
+            "--branching" => options.branching = value.parse().map_err(|_| bad_value())?,
+            "--curios" => options.curios = value.parse().map_err(|_| bad_value())?,
+            "--wumpus" => {
+                options.wumpus = match value.as_str() {
+                    "far" => Wumpus::Far,
+                    "random" => Wumpus::Random,
+                    room => Wumpus::Room(room.parse().map_err(|_| bad_value())?),
+                }
+            }
+            _ => return Err(format!("unknown option {}", flag)),
+        }
     }
+    Ok(options)
 }
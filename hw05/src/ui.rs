@@ -1,15 +1,30 @@
+use std::fs::File;
 use std::io::{self, Write};
 
+use rand::rngs::StdRng;
+
+use crate::game::board::Board;
 use crate::game::player::Command;
 use crate::game::player::Player;
+use crate::game::save;
 
 #[derive(Debug)]
 enum Error {
     Parse,
+}
+
+/// A line the player typed: either a command for the player, or one for the
+/// game itself.
+enum UiCommand {
+    Act(Command),
+    /// Save the game under this name.
+    Save(String),
+    /// Load the game saved under this name.
+    Load(String),
     Quit,
 }
 
-pub fn game_loop(mut player: Player) {
+pub fn game_loop(mut board: Board, mut player: Player, mut rng: StdRng) {
     loop {
         // Print a user input prompt.
         println!(
@@ -29,21 +44,48 @@ pub fn game_loop(mut player: Player) {
                 break;
             }
             Ok(_) => {
-                let parse = parse_line(&buf);
-                if let Err(Error::Parse) = parse {
-                    println!("I do not know how to {}!", buf.trim());
-                } else if let Err(Error::Quit) = parse {
-                    break;
-                } else if let Ok(cmd) = parse {
-                    if let Err(_) = player.act(cmd) {
-                        println!("I don't know how to {}!", buf.trim());
+                match parse_line(&buf) {
+                    Err(Error::Parse) => println!("I do not know how to {}!", buf.trim()),
+                    Ok(UiCommand::Quit) => break,
+                    Ok(UiCommand::Save(name)) => match save_path(&name) {
+                        Some(path) => {
+                            let res = File::create(&path).and_then(|mut file| save::save(&mut file, &board, &player));
+                            match res {
+                                Ok(()) => println!("Your progress is recorded in {}.", path),
+                                Err(err) => println!("Unable to save to {}: {}", path, err),
+                            }
+                        }
+                        None => println!("{:?} isn't a name a game can be saved under.", name),
+                    },
+                    Ok(UiCommand::Load(name)) => match save_path(&name) {
+                        Some(path) => match File::open(&path) {
+                            Ok(mut file) => match save::load(&mut file, &mut rng) {
+                                Ok((saved_board, saved_player)) => {
+                                    board = saved_board;
+                                    player = saved_player;
+                                    println!("You resume your adventure from {}.", path);
+                                }
+                                Err(err) => println!("Unable to load {}:\n{}", path, err),
+                            },
+                            Err(err) => println!("Unable to open {}: {}", path, err),
+                        },
+                        None => println!("{:?} isn't a name a game can be saved under.", name),
+                    },
+                    Ok(UiCommand::Act(cmd)) => {
+                        if let Err(room) = player.act(cmd, &mut rng) {
+                            println!("There's no room called {} next to you.", room);
+                        }
                     }
                 }
                 if player.hp <= 0 {
-                    println!(
-                        "You try in vain to shovel more wall chicken into \
-                              your mouth, but you've been impaled by too many spikes or Wumpi :("
-                    );
+                    if player.location.borrow().pit {
+                        println!("You're still falling, and you always will be :(");
+                    } else {
+                        println!(
+                            "You try in vain to shovel more wall chicken into \
+                                  your mouth, but you've been impaled by too many spikes or Wumpi :("
+                        );
+                    }
                     println!("You Lose!");
                     return;
                 }
@@ -53,23 +95,69 @@ pub fn game_loop(mut player: Player) {
     println!("Score: {}", player.gold * 1000);
 }
 
-fn parse_line(buf: &String) -> Result<Command, Error> {
+fn parse_line(buf: &str) -> Result<UiCommand, Error> {
     use crate::game::player::Command::*;
 
-    let tokens = buf.trim().split_whitespace();
-    let mut tokens = tokens.map(|t| String::from(t).to_lowercase());
+    let mut tokens = buf.split_whitespace().map(String::from);
 
-    let cmd = tokens.next().ok_or(Error::Parse)?;
+    let cmd = tokens.next().ok_or(Error::Parse)?.to_lowercase();
     if cmd == "go" {
         let room = tokens.next().ok_or(Error::Parse)?;
-        Ok(Go(room))
+        Ok(UiCommand::Act(Go(room)))
     } else if cmd == "shoot" {
         let room = tokens.next().ok_or(Error::Parse)?;
-        Ok(Shoot(room))
+        Ok(UiCommand::Act(Shoot(room)))
+    } else if cmd == "save" {
+        let name = tokens.next().ok_or(Error::Parse)?;
+        Ok(UiCommand::Save(name))
+    } else if cmd == "load" {
+        let name = tokens.next().ok_or(Error::Parse)?;
+        Ok(UiCommand::Load(name))
     } else if cmd == "quit" {
         println!("Bye forever :(");
-        Err(Error::Quit)
+        Ok(UiCommand::Quit)
     } else {
         Err(Error::Parse)
     }
 }
+
+/// The file a game is saved to under `name`, in the current directory. The
+/// name may only use letters, digits, `-` and `_`, so a save can't reach
+/// into another directory or replace a castle; `.json` is added unless it's
+/// already there.
+fn save_path(name: &str) -> Option<String> {
+    let stem = name.strip_suffix(".json").unwrap_or(name);
+    let plain = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if stem.is_empty() || !stem.chars().all(plain) {
+        return None;
+    }
+    Some(format!("{}.json", stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        assert!(matches!(parse_line("go Attic\n"), Ok(UiCommand::Act(Command::Go(room))) if room == "Attic"));
+        assert!(matches!(parse_line("SHOOT lair"), Ok(UiCommand::Act(Command::Shoot(room))) if room == "lair"));
+        assert!(matches!(parse_line("save slot1"), Ok(UiCommand::Save(name)) if name == "slot1"));
+        assert!(matches!(parse_line("load slot1"), Ok(UiCommand::Load(name)) if name == "slot1"));
+        assert!(matches!(parse_line("quit"), Ok(UiCommand::Quit)));
+        assert!(matches!(parse_line("save"), Err(Error::Parse)));
+        assert!(matches!(parse_line("dance"), Err(Error::Parse)));
+        assert!(matches!(parse_line(""), Err(Error::Parse)));
+    }
+
+    #[test]
+    fn test_save_path() {
+        assert_eq!(save_path("slot1"), Some("slot1.json".to_string()));
+        assert_eq!(save_path("my_game-2.json"), Some("my_game-2.json".to_string()));
+        assert_eq!(save_path("data/castle"), None);
+        assert_eq!(save_path("../castle"), None);
+        assert_eq!(save_path("/tmp/game"), None);
+        assert_eq!(save_path(".json"), None);
+        assert_eq!(save_path(""), None);
+    }
+}